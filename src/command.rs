@@ -0,0 +1,119 @@
+use std::{
+    io,
+    process::{Child, ChildStderr, ChildStdout, ExitStatus, Stdio},
+    time::{Duration, Instant},
+};
+
+/// A command whose output is viewed.
+pub struct Command {
+    args: Vec<String>,
+    child: Child,
+    started: Instant,
+    /// Exit status and the running time, once the command exited
+    exited: Option<(ExitStatus, Duration)>,
+}
+
+impl Command {
+    /// Starts the command given by `args`, returns it with its standard
+    /// output and standard error.
+    pub fn spawn(args: &[String]) -> io::Result<(Self, ChildStdout, ChildStderr)> {
+        let (child, stdout, stderr) = spawn_child(args)?;
+        let command = Self {
+            args: args.to_vec(),
+            child,
+            started: Instant::now(),
+            exited: None,
+        };
+        Ok((command, stdout, stderr))
+    }
+
+    /// Stops the command if it is still running and starts it again.
+    pub fn restart(&mut self) -> io::Result<(ChildStdout, ChildStderr)> {
+        self.stop();
+        let (child, stdout, stderr) = spawn_child(&self.args)?;
+        self.child = child;
+        self.started = Instant::now();
+        self.exited = None;
+        Ok((stdout, stderr))
+    }
+
+    /// Checks whether the command exited.
+    pub fn poll(&mut self) {
+        if self.exited.is_none() {
+            if let Ok(Some(status)) = self.child.try_wait() {
+                self.exited = Some((status, self.started.elapsed()));
+            }
+        }
+    }
+
+    /// Returns when the running time shown should change next.
+    pub fn next_second(&self) -> Option<Instant> {
+        if self.exited.is_some() {
+            return None;
+        }
+        let elapsed = self.started.elapsed();
+        Some(self.started + Duration::from_secs(elapsed.as_secs() + 1))
+    }
+
+    /// Returns a label, the command line, and details for the status bar.
+    pub fn describe(&self) -> (&'static str, String, String) {
+        let command_line = self.args.join(" ");
+        match self.exited {
+            None => (
+                "Running ",
+                command_line,
+                format!(
+                    ", PID {}, {}",
+                    self.child.id(),
+                    format_duration(self.started.elapsed())
+                ),
+            ),
+            Some((status, ran)) => (
+                "Exited ",
+                command_line,
+                format!(", {}, ran {}", status, format_duration(ran)),
+            ),
+        }
+    }
+
+    fn stop(&mut self) {
+        if self.exited.is_none() {
+            self.child.kill().ok();
+        }
+        self.child.wait().ok();
+    }
+}
+
+impl Drop for Command {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
+fn spawn_child(args: &[String]) -> io::Result<(Child, ChildStdout, ChildStderr)> {
+    let mut child = std::process::Command::new(&args[0])
+        .args(&args[1..])
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|error| io::Error::new(error.kind(), format!("{}: {}", args[0], error)))?;
+    let stdout = child.stdout.take().unwrap();
+    let stderr = child.stderr.take().unwrap();
+    Ok((child, stdout, stderr))
+}
+
+/// Formats a duration as `m:ss` or `h:mm:ss`.
+fn format_duration(duration: Duration) -> String {
+    let seconds = duration.as_secs();
+    if seconds < 3600 {
+        format!("{}:{:02}", seconds / 60, seconds % 60)
+    } else {
+        format!(
+            "{}:{:02}:{:02}",
+            seconds / 3600,
+            seconds / 60 % 60,
+            seconds % 60
+        )
+    }
+}
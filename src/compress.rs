@@ -0,0 +1,178 @@
+use crate::source::CHUNK_SIZE;
+use flate2::write::MultiGzDecoder;
+use std::{
+    fs::File,
+    io::{self, Cursor, Read, Seek, SeekFrom, Write},
+    path::Path,
+};
+
+/// Decompresses a file while it is read, it reads from its own handle of the
+/// file, which shares the position with the others.
+pub struct Decompressor {
+    format: Format,
+    file: File,
+    /// Decompressed data that was not read yet
+    output: Cursor<Vec<u8>>,
+    /// Set when the data is corrupt, nothing more is read after that
+    failed: bool,
+}
+
+enum Format {
+    /// Gzip is decompressed as it arrives, so members that are appended to
+    /// a growing file are read as well
+    Gzip(MultiGzDecoder<Vec<u8>>),
+    /// Zstandard and xz are read through a decoder, a new one is started
+    /// for frames or streams that follow the end of the previous one
+    Reader {
+        make: fn(File) -> io::Result<Box<dyn Read>>,
+        reader: Option<Box<dyn Read>>,
+        /// Where the frame that is decoded starts
+        start: u64,
+        /// Decompressed size of the frame up to now
+        decoded: u64,
+        /// Decompressed data to drop, it was already read before the frame
+        /// was decoded again
+        skip: u64,
+    },
+}
+
+impl Decompressor {
+    /// Returns a decompressor if the name of the file ends with `.gz`,
+    /// `.zst`, or `.xz`, or if its first bytes are those of one of these
+    /// formats.
+    pub fn detect(path: &Path, file: &File) -> io::Result<Option<Self>> {
+        let mut magic = [0; 6];
+        let mut handle = file.try_clone()?;
+        let position = handle.stream_position()?;
+        handle.seek(SeekFrom::Start(0))?;
+        let size = handle.read(&mut magic)?;
+        handle.seek(SeekFrom::Start(position))?;
+        let magic = &magic[..size];
+        let extension = path.extension().and_then(|extension| extension.to_str());
+        let format = if extension == Some("gz") || magic.starts_with(&[0x1f, 0x8b]) {
+            Format::Gzip(MultiGzDecoder::new(Vec::new()))
+        } else if extension == Some("zst") || magic.starts_with(&[0x28, 0xb5, 0x2f, 0xfd]) {
+            Format::Reader {
+                make: |file| {
+                    let decoder = ruzstd::decoding::StreamingDecoder::new(file)
+                        .map_err(|error| io::Error::new(io::ErrorKind::InvalidData, error))?;
+                    Ok(Box::new(decoder))
+                },
+                reader: None,
+                start: 0,
+                decoded: 0,
+                skip: 0,
+            }
+        } else if extension == Some("xz") || magic.starts_with(b"\xfd7zXZ\0") {
+            Format::Reader {
+                make: |file| Ok(Box::new(lzma_rust2::XzReader::new(file, true))),
+                reader: None,
+                start: 0,
+                decoded: 0,
+                skip: 0,
+            }
+        } else {
+            return Ok(None);
+        };
+        Ok(Some(Self {
+            format,
+            file: handle,
+            output: Cursor::new(Vec::new()),
+            failed: false,
+        }))
+    }
+
+    /// Puts decompressed data back, so it is read again before the rest.
+    pub fn unread(&mut self, mut data: Vec<u8>) {
+        let position = self.output.position() as usize;
+        data.extend_from_slice(&self.output.get_ref()[position..]);
+        self.output = Cursor::new(data);
+    }
+
+    /// Decompresses the next part of the file into `output`, returns false
+    /// when the end of the file is reached.
+    fn fill(&mut self) -> io::Result<bool> {
+        let mut buf = vec![0; CHUNK_SIZE as usize];
+        let size = match &mut self.format {
+            Format::Gzip(decoder) => {
+                let size = self.file.read(&mut buf)?;
+                if size == 0 {
+                    return Ok(false);
+                }
+                decoder.write_all(&buf[..size])?;
+                decoder.flush()?;
+                buf = std::mem::take(decoder.get_mut());
+                buf.len()
+            }
+            Format::Reader {
+                make,
+                reader,
+                start,
+                decoded,
+                skip,
+            } => {
+                let result = match reader {
+                    Some(reader) => reader.read(&mut buf),
+                    None => {
+                        // Wait until the next frame starts
+                        let position = self.file.stream_position()?;
+                        if position >= self.file.metadata()?.len() {
+                            return Ok(false);
+                        }
+                        if *skip == 0 {
+                            *start = position;
+                            *decoded = 0;
+                        }
+                        make(self.file.try_clone()?)
+                            .and_then(|new| reader.insert(new).read(&mut buf))
+                    }
+                };
+                let size = match result {
+                    Ok(size) => size,
+                    // A frame that is still being written ends early, it
+                    // is decoded again from its start once more was added
+                    Err(_) if self.file.stream_position()? >= self.file.metadata()?.len() => {
+                        *reader = None;
+                        *skip = *decoded;
+                        self.file.seek(SeekFrom::Start(*start))?;
+                        return Ok(false);
+                    }
+                    Err(error) => return Err(error),
+                };
+                if size == 0 {
+                    *reader = None;
+                    return Ok(self.file.stream_position()? < self.file.metadata()?.len());
+                }
+                buf.truncate(size);
+                let skipped = size.min(*skip as usize);
+                buf.drain(..skipped);
+                *skip -= skipped as u64;
+                *decoded += buf.len() as u64;
+                buf.len()
+            }
+        };
+        if size != 0 {
+            self.output = Cursor::new(buf);
+        }
+        Ok(true)
+    }
+}
+
+impl Read for Decompressor {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        loop {
+            let size = self.output.read(buf)?;
+            if size != 0 || self.failed {
+                return Ok(size);
+            }
+            match self.fill() {
+                Ok(true) => {}
+                Ok(false) => return Ok(0),
+                Err(error) => {
+                    self.failed = true;
+                    return Err(error);
+                }
+            }
+        }
+    }
+}
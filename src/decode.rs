@@ -0,0 +1,231 @@
+use clap::ValueEnum;
+
+/// Character encoding of a file.
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Encoding {
+    #[value(name = "utf-8")]
+    Utf8,
+    /// Little endian UTF-16, as written by most Windows programs
+    #[value(name = "utf-16le", alias = "utf-16")]
+    Utf16Le,
+    #[value(name = "utf-16be")]
+    Utf16Be,
+    /// ISO 8859-1
+    #[value(name = "latin-1", alias = "latin1")]
+    Latin1,
+}
+
+impl Encoding {
+    /// Returns where the next line starts if the character that ends with
+    /// `byte` at `position` is a newline. `before` is the byte in front of
+    /// it, if known, which UTF-16 newlines need to be told apart from other
+    /// characters.
+    pub fn line_start(self, position: u64, before: Option<u8>, byte: u8) -> Option<u64> {
+        let newline = match self {
+            Self::Utf8 | Self::Latin1 => byte == b'\n',
+            Self::Utf16Le => !position.is_multiple_of(2) && before == Some(b'\n') && byte == 0,
+            Self::Utf16Be => !position.is_multiple_of(2) && before == Some(0) && byte == b'\n',
+        };
+        newline.then_some(position + 1)
+    }
+
+    /// Rounds `position` down to the start of a UTF-16 code unit, so bytes
+    /// are paired the same way as from the start of the file.
+    pub fn align(self, position: u64) -> u64 {
+        match self {
+            Self::Utf8 | Self::Latin1 => position,
+            Self::Utf16Le | Self::Utf16Be => position & !1,
+        }
+    }
+}
+
+/// Decodes text that arrives in arbitrary pieces, characters split between
+/// two pieces are kept until the rest arrives.
+pub struct Decoder {
+    encoding: Encoding,
+    /// Start of an incomplete character
+    pending: Vec<u8>,
+    /// Whether nothing was decoded yet, so a byte order mark can be dropped
+    at_start: bool,
+}
+
+impl Decoder {
+    pub fn new(encoding: Encoding) -> Self {
+        Self {
+            encoding,
+            pending: Vec::new(),
+            at_start: true,
+        }
+    }
+
+    /// Forgets incomplete characters, for when reading continues somewhere
+    /// else. `at_start` tells whether that is the start of a file.
+    pub fn reset(&mut self, at_start: bool) {
+        self.pending.clear();
+        self.at_start = at_start;
+    }
+
+    /// Decodes `data` and appends the complete characters to `text`.
+    pub fn decode(&mut self, data: &[u8], text: &mut String) {
+        let start = text.len();
+        let mut bytes = std::mem::take(&mut self.pending);
+        bytes.extend_from_slice(data);
+        let rest = match self.encoding {
+            Encoding::Utf8 => decode_utf8(&bytes, text),
+            Encoding::Utf16Le => decode_utf16(&bytes, u16::from_le_bytes, text),
+            Encoding::Utf16Be => decode_utf16(&bytes, u16::from_be_bytes, text),
+            Encoding::Latin1 => {
+                text.extend(bytes.iter().map(|&byte| char::from(byte)));
+                0
+            }
+        };
+        self.pending = bytes.split_off(bytes.len() - rest);
+        if self.at_start && text.len() > start {
+            self.at_start = false;
+            if text[start..].starts_with('\u{feff}') {
+                text.replace_range(start..start + '\u{feff}'.len_utf8(), "");
+            }
+        }
+    }
+
+    /// Replaces an incomplete character at the end of a file, which will not
+    /// be completed anymore.
+    pub fn finish(&mut self, text: &mut String) {
+        if !self.pending.is_empty() {
+            self.pending.clear();
+            text.push(char::REPLACEMENT_CHARACTER);
+        }
+    }
+}
+
+/// Decodes UTF-8 replacing invalid bytes, returns the number of bytes at the
+/// end that may be the start of a character.
+fn decode_utf8(mut bytes: &[u8], text: &mut String) -> usize {
+    loop {
+        match std::str::from_utf8(bytes) {
+            Ok(valid) => {
+                text.push_str(valid);
+                return 0;
+            }
+            Err(error) => {
+                let (valid, invalid) = bytes.split_at(error.valid_up_to());
+                // Checked by from_utf8
+                text.push_str(std::str::from_utf8(valid).unwrap());
+                match error.error_len() {
+                    Some(length) => {
+                        text.push(char::REPLACEMENT_CHARACTER);
+                        bytes = &invalid[length..];
+                    }
+                    None => return invalid.len(),
+                }
+            }
+        }
+    }
+}
+
+/// Decodes UTF-16 replacing unpaired surrogates, returns the number of bytes
+/// at the end that may be the start of a character.
+fn decode_utf16(bytes: &[u8], unit: fn([u8; 2]) -> u16, text: &mut String) -> usize {
+    let mut units: Vec<u16> = bytes
+        .chunks_exact(2)
+        .map(|pair| unit([pair[0], pair[1]]))
+        .collect();
+    let mut rest = bytes.len() % 2;
+    // A high surrogate needs the next unit
+    if let Some(&last) = units.last() {
+        if (0xd800..0xdc00).contains(&last) {
+            units.pop();
+            rest += 2;
+        }
+    }
+    text.extend(char::decode_utf16(units).map(|c| c.unwrap_or(char::REPLACEMENT_CHARACTER)));
+    rest
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn decode(encoding: Encoding, pieces: &[&[u8]]) -> String {
+        let mut decoder = Decoder::new(encoding);
+        let mut text = String::new();
+        for piece in pieces {
+            decoder.decode(piece, &mut text);
+        }
+        decoder.finish(&mut text);
+        text
+    }
+
+    #[test]
+    fn utf8_split_between_pieces() {
+        let bytes = "añ€😀".as_bytes();
+        for split in 0..=bytes.len() {
+            let (first, second) = bytes.split_at(split);
+            assert_eq!(decode(Encoding::Utf8, &[first, second]), "añ€😀");
+        }
+    }
+
+    #[test]
+    fn utf8_invalid_and_incomplete() {
+        assert_eq!(decode(Encoding::Utf8, &[b"a\xffb"]), "a\u{fffd}b");
+        // The start of a character at the end of the file is not completed
+        assert_eq!(decode(Encoding::Utf8, &[b"a\xe2\x82"]), "a\u{fffd}");
+    }
+
+    #[test]
+    fn utf16_surrogates() {
+        let le: Vec<u8> = "a😀".encode_utf16().flat_map(u16::to_le_bytes).collect();
+        for split in 0..=le.len() {
+            let (first, second) = le.split_at(split);
+            assert_eq!(decode(Encoding::Utf16Le, &[first, second]), "a😀");
+        }
+        let be: Vec<u8> = "a😀".encode_utf16().flat_map(u16::to_be_bytes).collect();
+        assert_eq!(decode(Encoding::Utf16Be, &[&be[..3], &be[3..]]), "a😀");
+        // Unpaired surrogates
+        assert_eq!(decode(Encoding::Utf16Le, &[b"\x00\xdca\x00"]), "\u{fffd}a");
+        assert_eq!(decode(Encoding::Utf16Le, &[b"a\x00\x3d\xd8"]), "a\u{fffd}");
+    }
+
+    #[test]
+    fn byte_order_mark() {
+        assert_eq!(decode(Encoding::Utf8, &[b"\xef\xbb", b"\xbfa"]), "a");
+        assert_eq!(decode(Encoding::Utf16Le, &[b"\xff\xfea\x00"]), "a");
+        assert_eq!(decode(Encoding::Utf16Be, &[b"\xfe\xff\x00a"]), "a");
+        // Only at the start of the file
+        assert_eq!(
+            decode(Encoding::Utf8, &[b"a", "\u{feff}b".as_bytes()]),
+            "a\u{feff}b"
+        );
+        let mut decoder = Decoder::new(Encoding::Utf8);
+        decoder.reset(false);
+        let mut text = String::new();
+        decoder.decode("\u{feff}a".as_bytes(), &mut text);
+        assert_eq!(text, "\u{feff}a");
+    }
+
+    #[test]
+    fn latin1() {
+        assert_eq!(decode(Encoding::Latin1, &[b"caf\xe9"]), "café");
+    }
+
+    #[test]
+    fn utf16_newlines() {
+        // U+010A and U+0A0A contain the byte of a newline
+        let le: Vec<u8> = "\u{10a}\u{a0a}\n"
+            .encode_utf16()
+            .flat_map(u16::to_le_bytes)
+            .collect();
+        let starts: Vec<u64> = (1..le.len())
+            .filter_map(|i| Encoding::Utf16Le.line_start(i as u64, Some(le[i - 1]), le[i]))
+            .collect();
+        assert_eq!(starts, [6]);
+        let be: Vec<u8> = "\u{10a}\u{a0a}\n"
+            .encode_utf16()
+            .flat_map(u16::to_be_bytes)
+            .collect();
+        let starts: Vec<u64> = (1..be.len())
+            .filter_map(|i| Encoding::Utf16Be.line_start(i as u64, Some(be[i - 1]), be[i]))
+            .collect();
+        assert_eq!(starts, [6]);
+    }
+}
@@ -0,0 +1,382 @@
+use clap::ValueEnum;
+
+/// Which escape sequences from files are passed to the terminal.
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Escapes {
+    /// Everything, including control characters
+    All,
+    /// Only SGR sequences, which set colors and styles
+    Sgr,
+    /// Nothing
+    None,
+}
+
+/// What kind of escape sequence was found.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Kind {
+    /// Control sequence (`ESC [`), such as SGR or cursor movement
+    Csi,
+    /// Operating system command (`ESC ]`), such as window titles and
+    /// hyperlinks
+    Osc,
+    /// Device control string, start of string, privacy message, or
+    /// application program command, which are all terminated like OSC
+    String,
+    /// Single shift (`ESC N` or `ESC O`) with the character it applies to
+    SingleShift,
+    /// Any other sequence of ESC, intermediate bytes, and a final byte
+    Esc,
+    /// A sequence that is cut off by the end of the line, or a lone ESC
+    Incomplete,
+}
+
+/// An escape sequence at some position of a line.
+#[derive(Clone, Copy)]
+pub struct Sequence {
+    pub kind: Kind,
+    /// Number of characters, including the ESC
+    pub length: usize,
+    /// Whether this is a plain SGR sequence (`ESC [ ... m`), which only
+    /// changes the style of the following text
+    pub sgr: bool,
+}
+
+/// Parses the escape sequence starting with the ESC at `i`, following the
+/// states of a DEC compatible terminal.
+pub fn parse(line: &[char], i: usize) -> Sequence {
+    let sequence = |kind, end: usize| Sequence {
+        kind,
+        length: end - i,
+        sgr: false,
+    };
+    let mut j = i + 1;
+    let Some(&c) = line.get(j) else {
+        return sequence(Kind::Incomplete, j);
+    };
+    j += 1;
+    match c {
+        '[' => {
+            let mut private = false;
+            let mut intermediate = false;
+            while let Some(&c) = line.get(j) {
+                match c {
+                    // Parameter bytes, `<=>?` mark private sequences
+                    '0'..='9' | ':' | ';' => {}
+                    '<'..='?' => private = true,
+                    // Intermediate bytes
+                    ' '..='/' => intermediate = true,
+                    // Final byte
+                    '@'..='~' => {
+                        return Sequence {
+                            sgr: c == 'm' && !private && !intermediate,
+                            ..sequence(Kind::Csi, j + 1)
+                        };
+                    }
+                    // Anything else cancels the sequence and is shown as text
+                    _ => return sequence(Kind::Incomplete, j),
+                }
+                j += 1;
+            }
+            sequence(Kind::Incomplete, j)
+        }
+        ']' | 'P' | 'X' | '^' | '_' => {
+            let kind = if c == ']' { Kind::Osc } else { Kind::String };
+            while let Some(&c) = line.get(j) {
+                match c {
+                    // BEL or the 8-bit string terminator
+                    '\x07' | '\u{9c}' => return sequence(kind, j + 1),
+                    // ESC \ is the string terminator, any other ESC cancels
+                    // the string and starts a new sequence
+                    '\x1b' if line.get(j + 1) == Some(&'\\') => return sequence(kind, j + 2),
+                    '\x1b' => return sequence(Kind::Incomplete, j),
+                    _ => {}
+                }
+                j += 1;
+            }
+            sequence(Kind::Incomplete, j)
+        }
+        'N' | 'O' => match line.get(j) {
+            Some(_) => sequence(Kind::SingleShift, j + 1),
+            None => sequence(Kind::Incomplete, j),
+        },
+        ' '..='/' => {
+            while let Some(&c) = line.get(j) {
+                match c {
+                    ' '..='/' => {}
+                    '0'..='~' => return sequence(Kind::Esc, j + 1),
+                    _ => return sequence(Kind::Incomplete, j),
+                }
+                j += 1;
+            }
+            sequence(Kind::Incomplete, j)
+        }
+        '0'..='~' => sequence(Kind::Esc, j),
+        // A control character or anything else, the ESC stands alone
+        _ => sequence(Kind::Incomplete, i + 1),
+    }
+}
+
+/// Text attributes set by SGR sequences, so they can be applied again after
+/// they were reset.
+#[derive(Clone, Default, PartialEq, Eq)]
+pub struct Sgr {
+    bold: bool,
+    dim: bool,
+    italic: bool,
+    /// Parameter of the underline style, `4`, `21`, or `4:n`
+    underline: Option<String>,
+    blink: Option<&'static str>,
+    reverse: bool,
+    hidden: bool,
+    strike: bool,
+    overline: bool,
+    /// Parameters of the colors, such as `31` or `38;5;208`
+    foreground: Option<String>,
+    background: Option<String>,
+    underline_color: Option<String>,
+}
+
+impl Sgr {
+    /// Updates the attributes with an SGR sequence (`ESC [ ... m`).
+    pub fn apply(&mut self, seq: &[char]) {
+        let params: String = seq[2..seq.len() - 1].iter().collect();
+        let mut params = params.split(';');
+        while let Some(param) = params.next() {
+            // Colors with more parameters, using either colons or semicolons
+            let mut color = |param: &str| {
+                if param.contains(':') {
+                    return param.to_string();
+                }
+                let count = match params.next() {
+                    Some("5") => 1,
+                    Some("2") => 3,
+                    Some(kind) => return format!("{param};{kind}"),
+                    None => return param.to_string(),
+                };
+                let kind = if count == 1 { "5" } else { "2" };
+                let values: Vec<&str> = params.by_ref().take(count).collect();
+                format!("{param};{kind};{}", values.join(";"))
+            };
+            match param {
+                "" | "0" => *self = Self::default(),
+                "1" => self.bold = true,
+                "2" => self.dim = true,
+                "3" => self.italic = true,
+                "4:0" | "24" => self.underline = None,
+                "4" | "21" => self.underline = Some(param.to_string()),
+                _ if param.starts_with("4:") => self.underline = Some(param.to_string()),
+                "5" => self.blink = Some("5"),
+                "6" => self.blink = Some("6"),
+                "7" => self.reverse = true,
+                "8" => self.hidden = true,
+                "9" => self.strike = true,
+                "22" => (self.bold, self.dim) = (false, false),
+                "23" => self.italic = false,
+                "25" => self.blink = None,
+                "27" => self.reverse = false,
+                "28" => self.hidden = false,
+                "29" => self.strike = false,
+                "39" => self.foreground = None,
+                "49" => self.background = None,
+                "53" => self.overline = true,
+                "55" => self.overline = false,
+                "59" => self.underline_color = None,
+                _ if param.starts_with("38") => self.foreground = Some(color(param)),
+                _ if param.starts_with("48") => self.background = Some(color(param)),
+                _ if param.starts_with("58") => self.underline_color = Some(color(param)),
+                _ => match param.parse::<u8>() {
+                    Ok(30..=37 | 90..=97) => self.foreground = Some(param.to_string()),
+                    Ok(40..=47 | 100..=107) => self.background = Some(param.to_string()),
+                    _ => {}
+                },
+            }
+        }
+    }
+
+    /// Returns whether no attributes are set.
+    pub fn is_default(&self) -> bool {
+        *self == Self::default()
+    }
+
+    /// Returns an SGR sequence that sets the attributes, or nothing if none
+    /// are set.
+    pub fn sequence(&self) -> String {
+        let flags = [
+            (self.bold, "1"),
+            (self.dim, "2"),
+            (self.italic, "3"),
+            (self.reverse, "7"),
+            (self.hidden, "8"),
+            (self.strike, "9"),
+            (self.overline, "53"),
+        ];
+        let params: Vec<&str> = flags
+            .into_iter()
+            .filter_map(|(set, param)| set.then_some(param))
+            .chain(self.blink)
+            .chain(
+                [
+                    &self.underline,
+                    &self.foreground,
+                    &self.background,
+                    &self.underline_color,
+                ]
+                .into_iter()
+                .flatten()
+                .map(String::as_str),
+            )
+            .collect();
+        if params.is_empty() {
+            String::new()
+        } else {
+            format!("\x1b[{}m", params.join(";"))
+        }
+    }
+}
+
+/// Makes escape sequences that are not allowed by `escapes` and control
+/// characters visible using caret notation, such as `^[[2J`.
+pub fn sanitize(text: String, escapes: Escapes) -> String {
+    if escapes == Escapes::All || !text.contains(|c: char| c.is_control() && c != '\t') {
+        return text;
+    }
+    let chars: Vec<char> = text.chars().collect();
+    let mut result = String::with_capacity(text.len());
+    let mut i = 0;
+    while i < chars.len() {
+        if chars[i] == '\x1b' {
+            let sequence = parse(&chars, i);
+            let seq = &chars[i..i + sequence.length];
+            if sequence.sgr && escapes == Escapes::Sgr {
+                result.extend(seq);
+            } else {
+                seq.iter().for_each(|&c| push_visible(&mut result, c));
+            }
+            i += sequence.length;
+        } else {
+            push_visible(&mut result, chars[i]);
+            i += 1;
+        }
+    }
+    result
+}
+
+fn push_visible(result: &mut String, c: char) {
+    match c {
+        // Tabs are expanded to spaces later
+        '\t' => result.push(c),
+        '\0'..='\x1f' => {
+            result.push('^');
+            result.push((c as u8 + 0x40) as char);
+        }
+        '\x7f' => result.push_str("^?"),
+        // C1 controls are the same as ESC followed by a character
+        '\u{80}'..='\u{9f}' => {
+            result.push_str("^[");
+            result.push(char::from_u32(c as u32 - 0x40).unwrap());
+        }
+        _ => result.push(c),
+    }
+}
+
+/// Removes all escape sequences from `line`, also returns the index of each
+/// remaining character in `line`.
+pub fn strip(line: &[char]) -> (String, Vec<usize>) {
+    let mut text = String::new();
+    let mut indices = Vec::new();
+    let mut i = 0;
+    while i < line.len() {
+        if line[i] == '\x1b' {
+            i += parse(line, i).length;
+            continue;
+        }
+        text.push(line[i]);
+        indices.push(i);
+        i += 1;
+    }
+    (text, indices)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Parses the sequence at the start of `text`, returns its kind, its
+    /// length, and whether it is SGR.
+    fn parse_str(text: &str) -> (Kind, usize, bool) {
+        let chars: Vec<char> = text.chars().collect();
+        let sequence = parse(&chars, 0);
+        (sequence.kind, sequence.length, sequence.sgr)
+    }
+
+    #[test]
+    fn csi() {
+        assert_eq!(parse_str("\x1b[1;31mred"), (Kind::Csi, 7, true));
+        assert_eq!(parse_str("\x1b[38:5:208m"), (Kind::Csi, 11, true));
+        assert_eq!(parse_str("\x1b[mx"), (Kind::Csi, 3, true));
+        assert_eq!(parse_str("\x1b[2J"), (Kind::Csi, 4, false));
+        // Private and intermediate bytes make it something else than SGR
+        assert_eq!(parse_str("\x1b[?25l"), (Kind::Csi, 6, false));
+        assert_eq!(parse_str("\x1b[>4;2m"), (Kind::Csi, 7, false));
+        assert_eq!(parse_str("\x1b[0 q"), (Kind::Csi, 5, false));
+    }
+
+    #[test]
+    fn csi_cancelled_or_cut_off() {
+        // A control character cancels the sequence and is shown
+        assert_eq!(parse_str("\x1b[31\nm"), (Kind::Incomplete, 4, false));
+        assert_eq!(parse_str("\x1b[31"), (Kind::Incomplete, 4, false));
+        assert_eq!(parse_str("\x1b["), (Kind::Incomplete, 2, false));
+    }
+
+    #[test]
+    fn strings() {
+        let link = "\x1b]8;;https://example.com\x1b\\";
+        assert_eq!(parse_str(&format!("{link}text")), (Kind::Osc, 26, false));
+        assert_eq!(parse_str("\x1b]0;title\x07rest"), (Kind::Osc, 10, false));
+        assert_eq!(parse_str("\x1b]0;title\u{9c}"), (Kind::Osc, 10, false));
+        assert_eq!(parse_str("\x1bPq#0\x1b\\"), (Kind::String, 7, false));
+        assert_eq!(parse_str("\x1b_app\x07"), (Kind::String, 6, false));
+        // Another ESC starts a new sequence
+        assert_eq!(
+            parse_str("\x1b]0;title\x1b[1m"),
+            (Kind::Incomplete, 9, false)
+        );
+        assert_eq!(parse_str("\x1b]0;title"), (Kind::Incomplete, 9, false));
+    }
+
+    #[test]
+    fn other_sequences() {
+        assert_eq!(parse_str("\x1bNa"), (Kind::SingleShift, 3, false));
+        assert_eq!(parse_str("\x1bO"), (Kind::Incomplete, 2, false));
+        assert_eq!(parse_str("\x1b(B"), (Kind::Esc, 3, false));
+        assert_eq!(parse_str("\x1b#8"), (Kind::Esc, 3, false));
+        assert_eq!(parse_str("\x1b7"), (Kind::Esc, 2, false));
+        assert_eq!(parse_str("\x1bc"), (Kind::Esc, 2, false));
+        assert_eq!(parse_str("\x1b("), (Kind::Incomplete, 2, false));
+        // A lone ESC
+        assert_eq!(parse_str("\x1b"), (Kind::Incomplete, 1, false));
+        assert_eq!(parse_str("\x1b\x1b[1m"), (Kind::Incomplete, 1, false));
+        assert_eq!(parse_str("\x1b\n"), (Kind::Incomplete, 1, false));
+    }
+
+    #[test]
+    fn parse_in_the_middle() {
+        let chars: Vec<char> = "ab\x1b[1mc".chars().collect();
+        let sequence = parse(&chars, 2);
+        assert_eq!((sequence.kind, sequence.length), (Kind::Csi, 4));
+        assert_eq!(strip(&chars), ("abc".to_string(), vec![0, 1, 6]));
+    }
+
+    #[test]
+    fn sanitize_escapes() {
+        let text = "\x1b[1mbold\x1b[0m \x1b[2J\x07".to_string();
+        assert_eq!(sanitize(text.clone(), Escapes::All), text);
+        assert_eq!(
+            sanitize(text.clone(), Escapes::Sgr),
+            "\x1b[1mbold\x1b[0m ^[[2J^G"
+        );
+        assert_eq!(sanitize(text, Escapes::None), "^[[1mbold^[[0m ^[[2J^G");
+        assert_eq!(sanitize("a\u{9b}b".to_string(), Escapes::None), "a^[[b");
+    }
+}
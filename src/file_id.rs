@@ -0,0 +1,41 @@
+use std::{fs::File, io};
+
+#[cfg(target_family = "unix")]
+mod detail {
+    use std::{fs::File, io, os::unix::fs::MetadataExt};
+
+    pub type FileId = (u64, u64);
+
+    pub fn file_id(file: &File) -> io::Result<FileId> {
+        let metadata = file.metadata()?;
+        Ok((metadata.dev(), metadata.ino()))
+    }
+}
+
+#[cfg(target_family = "windows")]
+mod detail {
+    use std::{fs::File, io, os::windows::io::AsRawHandle};
+    use windows::Win32::{
+        Foundation::HANDLE,
+        Storage::FileSystem::{GetFileInformationByHandle, BY_HANDLE_FILE_INFORMATION},
+    };
+
+    /// Serial number of the volume and index of the file on it.
+    pub type FileId = (u32, u64);
+
+    pub fn file_id(file: &File) -> io::Result<FileId> {
+        let mut info = BY_HANDLE_FILE_INFORMATION::default();
+        unsafe {
+            GetFileInformationByHandle(HANDLE(file.as_raw_handle() as isize), &mut info).ok()?;
+        }
+        let index = (u64::from(info.nFileIndexHigh) << 32) | u64::from(info.nFileIndexLow);
+        Ok((info.dwVolumeSerialNumber, index))
+    }
+}
+
+pub use detail::FileId;
+
+/// Returns a value identifying the file itself rather than its name.
+pub fn file_id(file: &File) -> io::Result<FileId> {
+    detail::file_id(file)
+}
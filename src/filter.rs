@@ -0,0 +1,49 @@
+use crate::escape::strip;
+use regex::Regex;
+
+/// Decides which lines are shown.
+#[derive(Clone, Default)]
+pub struct Filter {
+    /// If not empty, lines need to match one of these to be shown
+    include: Vec<Regex>,
+    /// Lines matching any of these are not shown
+    exclude: Vec<Regex>,
+}
+
+impl Filter {
+    pub fn new(include: &[String], exclude: &[String]) -> Result<Self, regex::Error> {
+        let compile = |patterns: &[String]| {
+            patterns
+                .iter()
+                .map(|pattern| Regex::new(pattern))
+                .collect::<Result<Vec<_>, _>>()
+        };
+        Ok(Self {
+            include: compile(include)?,
+            exclude: compile(exclude)?,
+        })
+    }
+
+    pub fn is_active(&self) -> bool {
+        !(self.include.is_empty() && self.exclude.is_empty())
+    }
+
+    /// Returns whether `line` should be shown, escape sequences in the line
+    /// are ignored.
+    pub fn matches(&self, line: &str) -> bool {
+        if !self.is_active() {
+            return true;
+        }
+        let chars: Vec<char> = line.chars().collect();
+        let text = strip(&chars).0;
+        (self.include.is_empty() || self.include.iter().any(|regex| regex.is_match(&text)))
+            && !self.exclude.iter().any(|regex| regex.is_match(&text))
+    }
+
+    /// Returns a short description for the status bar.
+    pub fn describe(&self) -> String {
+        let include = self.include.iter().map(|regex| format!("/{}/", regex));
+        let exclude = self.exclude.iter().map(|regex| format!("!/{}/", regex));
+        include.chain(exclude).collect::<Vec<_>>().join(" ")
+    }
+}
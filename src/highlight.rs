@@ -0,0 +1,88 @@
+use regex::Regex;
+
+/// Colors text matching a pattern.
+#[derive(Clone)]
+pub struct Rule {
+    pub regex: Regex,
+    /// Parameters of the SGR sequence used for matches
+    pub style: String,
+}
+
+impl Rule {
+    /// Parses a rule given as `PATTERN=STYLE`, where the style is a comma
+    /// separated list of style names or SGR parameters.
+    pub fn parse(spec: &str) -> Result<Self, String> {
+        let (pattern, style) = spec
+            .rsplit_once('=')
+            .ok_or_else(|| format!("{spec}: expected PATTERN=STYLE"))?;
+        Ok(Self {
+            regex: Regex::new(pattern).map_err(|error| format!("{pattern}: {error}"))?,
+            style: parse_style(style)?,
+        })
+    }
+}
+
+/// Parses a comma separated list of style names or SGR parameters, returns
+/// the parameters of the SGR sequence.
+pub fn parse_style(style: &str) -> Result<String, String> {
+    Ok(style
+        .split(',')
+        .map(|name| style_code(name.trim()).ok_or_else(|| format!("{name}: unknown style")))
+        .collect::<Result<Vec<_>, _>>()?
+        .join(";"))
+}
+
+/// Rules used unless disabled, for log levels and common identifiers.
+pub fn builtin_rules() -> Vec<Rule> {
+    [
+        (r"\b(FATAL|PANIC|CRIT(ICAL)?|ERR(OR)?)\b", "1;31"),
+        (r"\bWARN(ING)?\b", "1;33"),
+        (r"\bINFO\b", "32"),
+        (r"\b(DEBUG|TRACE)\b", "34"),
+        (
+            r"\b[0-9a-fA-F]{8}-[0-9a-fA-F]{4}-[0-9a-fA-F]{4}-[0-9a-fA-F]{4}-[0-9a-fA-F]{12}\b",
+            "36",
+        ),
+        (r"\b\d{1,3}(\.\d{1,3}){3}(:\d+)?\b", "35"),
+    ]
+    .into_iter()
+    .map(|(pattern, style)| Rule {
+        regex: Regex::new(pattern).unwrap(),
+        style: style.to_string(),
+    })
+    .collect()
+}
+
+fn style_code(name: &str) -> Option<String> {
+    const COLORS: [&str; 8] = [
+        "black", "red", "green", "yellow", "blue", "magenta", "cyan", "white",
+    ];
+    if !name.is_empty() && name.chars().all(|c| c.is_ascii_digit() || c == ';') {
+        return Some(name.to_string());
+    }
+    let code = match name {
+        "bold" => 1,
+        "dim" => 2,
+        "italic" => 3,
+        "underline" => 4,
+        "reverse" => 7,
+        _ => {
+            if let Some(i) = COLORS.iter().position(|&color| color == name) {
+                30 + i
+            } else if let Some(i) = name
+                .strip_prefix("bright-")
+                .and_then(|name| COLORS.iter().position(|&color| color == name))
+            {
+                90 + i
+            } else if let Some(i) = name
+                .strip_prefix("on-")
+                .and_then(|name| COLORS.iter().position(|&color| color == name))
+            {
+                40 + i
+            } else {
+                return None;
+            }
+        }
+    };
+    Some(code.to_string())
+}
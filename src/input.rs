@@ -0,0 +1,96 @@
+use std::{
+    fs::File,
+    io::{self, Read},
+    thread,
+};
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Key {
+    Char(char),
+    Up,
+    Down,
+    Left,
+    Right,
+    PageUp,
+    PageDown,
+    Home,
+    End,
+    Tab,
+    Enter,
+    Backspace,
+    Escape,
+}
+
+/// Opens the terminal for reading keys, standard input may be a pipe that
+/// is being viewed.
+pub fn open_terminal() -> io::Result<File> {
+    let name = if cfg!(target_family = "windows") {
+        "CONIN$"
+    } else {
+        "/dev/tty"
+    };
+    File::options().read(true).write(true).open(name)
+}
+
+/// Reads keys from the terminal on a separate thread and calls `on_key` for
+/// each of them.
+pub fn spawn(mut terminal: File, mut on_key: impl FnMut(Key) + Send + 'static) {
+    thread::spawn(move || {
+        let mut buf = [0; 64];
+        loop {
+            let n = match terminal.read(&mut buf) {
+                Ok(0) | Err(_) => return,
+                Ok(n) => n,
+            };
+            for key in parse_keys(&buf[..n]) {
+                on_key(key);
+            }
+        }
+    });
+}
+
+fn parse_keys(data: &[u8]) -> Vec<Key> {
+    let mut keys = Vec::new();
+    let text = String::from_utf8_lossy(data);
+    let chars: Vec<char> = text.chars().collect();
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        i += 1;
+        let key = match c {
+            '\x1b' if i < chars.len() && (chars[i] == '[' || chars[i] == 'O') => {
+                // Consume parameters up to the final byte
+                let start = i + 1;
+                i = start;
+                while i < chars.len() && !('\x40'..='\x7e').contains(&chars[i]) {
+                    i += 1;
+                }
+                if i == chars.len() {
+                    break;
+                }
+                let params: String = chars[start..i].iter().collect();
+                let last = chars[i];
+                i += 1;
+                match (last, params.as_str()) {
+                    ('A', _) => Key::Up,
+                    ('B', _) => Key::Down,
+                    ('C', _) => Key::Right,
+                    ('D', _) => Key::Left,
+                    ('H', _) | ('~', "1" | "7") => Key::Home,
+                    ('F', _) | ('~', "4" | "8") => Key::End,
+                    ('~', "5") => Key::PageUp,
+                    ('~', "6") => Key::PageDown,
+                    _ => continue,
+                }
+            }
+            '\x1b' => Key::Escape,
+            '\t' => Key::Tab,
+            '\r' | '\n' => Key::Enter,
+            '\x7f' | '\x08' => Key::Backspace,
+            c if c.is_control() => continue,
+            c => Key::Char(c),
+        };
+        keys.push(key);
+    }
+    keys
+}
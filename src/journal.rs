@@ -0,0 +1,308 @@
+use chrono::{Local, TimeZone};
+
+/// Largest value that is taken for a binary field, like the limit of
+/// `systemd-journal-remote`. Larger sizes come from reading text as a size.
+const MAX_VALUE_SIZE: u64 = 768 * 1024 * 1024;
+
+/// Parses the journal export format, as written by `journalctl -o export`,
+/// which arrives in arbitrary pieces like any other file.
+///
+/// Entries are separated by an empty line. Each field is either a line of
+/// the form `KEY=value`, or the key on its own line followed by the length
+/// of the value as a 64 bit little endian number, the value, and a newline.
+pub struct Parser {
+    /// Start of an incomplete field
+    pending: Vec<u8>,
+    entry: Entry,
+    /// Whether reading started in the middle of an entry, which is skipped
+    resync: bool,
+}
+
+/// The fields of an entry that are shown.
+#[derive(Default)]
+struct Entry {
+    /// Microseconds since the epoch
+    timestamp: Option<i64>,
+    unit: Option<String>,
+    identifier: Option<String>,
+    priority: Option<u8>,
+    message: Option<String>,
+}
+
+impl Parser {
+    pub fn new(at_start: bool) -> Self {
+        Self {
+            pending: Vec::new(),
+            entry: Entry::default(),
+            resync: !at_start,
+        }
+    }
+
+    /// Forgets the incomplete entry, for when reading continues somewhere
+    /// else. `at_start` tells whether that is the start of a file.
+    pub fn reset(&mut self, at_start: bool) {
+        *self = Self::new(at_start);
+    }
+
+    /// Parses `data` and appends complete entries to `lines`, formatted as
+    /// lines with the SGR parameters for their priority.
+    pub fn parse(&mut self, data: &[u8], lines: &mut Vec<(String, Option<&'static str>)>) {
+        self.pending.extend_from_slice(data);
+        let mut start = 0;
+        loop {
+            let rest = &self.pending[start..];
+            if self.resync {
+                // Skip to the empty line after the entry
+                match rest.windows(2).position(|pair| pair == b"\n\n") {
+                    Some(end) => {
+                        start += end + 2;
+                        self.resync = false;
+                        continue;
+                    }
+                    None => {
+                        // Keep a newline that may be followed by another
+                        start += rest.len().saturating_sub(1);
+                        break;
+                    }
+                }
+            }
+            let Some(end) = rest.iter().position(|&byte| byte == b'\n') else {
+                break;
+            };
+            if end == 0 {
+                self.emit(lines);
+                start += 1;
+                continue;
+            }
+            let line = &rest[..end];
+            let equals = line.iter().position(|&byte| byte == b'=');
+            let key = &line[..equals.unwrap_or(end)];
+            if !is_key(key) {
+                // Text after an empty line inside a binary value, which was
+                // taken for the end of an entry
+                self.corrupt();
+                // Keep the newline, it may end the entry
+                start += end;
+                continue;
+            }
+            if let Some(equals) = equals {
+                self.entry.set(key, &line[equals + 1..]);
+                start += end + 1;
+                continue;
+            }
+            // Binary field, which may contain newlines
+            let Some(size) = rest.get(end + 1..end + 9) else {
+                break;
+            };
+            let size = u64::from_le_bytes(size.try_into().unwrap());
+            if size > MAX_VALUE_SIZE {
+                self.corrupt();
+                start += end;
+                continue;
+            }
+            let value_start = end + 9;
+            let value_end = value_start + size as usize;
+            match rest.get(value_end) {
+                Some(b'\n') => {}
+                Some(_) => {
+                    self.corrupt();
+                    start += end;
+                    continue;
+                }
+                None => break,
+            }
+            self.entry.set(line, &rest[value_start..value_end]);
+            // Also skip the newline after the value
+            start += value_end + 1;
+        }
+        self.pending.drain(..start);
+    }
+
+    /// Drops the entry that is being read and skips to the next one.
+    fn corrupt(&mut self) {
+        self.entry = Entry::default();
+        self.resync = true;
+    }
+
+    /// Emits the entry that was read so far, for when nothing will be
+    /// added to it.
+    pub fn finish(&mut self, lines: &mut Vec<(String, Option<&'static str>)>) {
+        self.pending.clear();
+        self.emit(lines);
+    }
+
+    fn emit(&mut self, lines: &mut Vec<(String, Option<&'static str>)>) {
+        let entry = std::mem::take(&mut self.entry);
+        if let Some(message) = &entry.message {
+            let style = entry.priority.and_then(priority_style);
+            let prefix = entry.prefix();
+            let indent = " ".repeat(prefix.chars().count());
+            // Messages with several lines are continued below the first
+            for (i, text) in message.lines().enumerate() {
+                let prefix = if i == 0 { &prefix } else { &indent };
+                lines.push((format!("{prefix}{text}"), style));
+            }
+        }
+    }
+}
+
+impl Entry {
+    fn set(&mut self, key: &[u8], value: &[u8]) {
+        let value = || String::from_utf8_lossy(value).into_owned();
+        match key {
+            b"__REALTIME_TIMESTAMP" => self.timestamp = value().parse().ok(),
+            b"_SYSTEMD_UNIT" => self.unit = Some(value()),
+            b"SYSLOG_IDENTIFIER" => self.identifier = Some(value()),
+            b"PRIORITY" => self.priority = value().parse().ok(),
+            b"MESSAGE" => self.message = Some(value()),
+            _ => {}
+        }
+    }
+
+    /// Returns the local time and the unit that logged the entry, like
+    /// `journalctl` shows them.
+    fn prefix(&self) -> String {
+        let mut prefix = String::new();
+        let time = self.timestamp.and_then(|micros| {
+            let nanos = (micros.rem_euclid(1_000_000) * 1000) as u32;
+            Local
+                .timestamp_opt(micros.div_euclid(1_000_000), nanos)
+                .single()
+        });
+        if let Some(time) = time {
+            prefix.push_str(&time.format("%b %d %H:%M:%S ").to_string());
+        }
+        if let Some(unit) = self.unit.as_ref().or(self.identifier.as_ref()) {
+            prefix.push_str(unit);
+            prefix.push_str(": ");
+        }
+        prefix
+    }
+}
+
+/// Whether `key` is a valid field name, which consists of uppercase
+/// letters, digits and underscores.
+fn is_key(key: &[u8]) -> bool {
+    !key.is_empty()
+        && key
+            .iter()
+            .all(|&byte| byte.is_ascii_uppercase() || byte.is_ascii_digit() || byte == b'_')
+}
+
+/// SGR parameters for a syslog priority, from emerg (0) to debug (7).
+fn priority_style(priority: u8) -> Option<&'static str> {
+    match priority {
+        0..=2 => Some("1;31"),
+        3 => Some("31"),
+        4 => Some("33"),
+        5 => Some("1"),
+        7 => Some("2"),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Three entries as written by `journalctl -o export`, the message of
+    /// the second one is a binary field.
+    const EXPORT: &[u8] = include_bytes!("../tests/data/journal.export");
+
+    fn parse(parser: &mut Parser, data: &[u8]) -> Vec<(String, Option<&'static str>)> {
+        let mut lines = Vec::new();
+        parser.parse(data, &mut lines);
+        lines
+    }
+
+    #[test]
+    fn text_and_binary_fields() {
+        let lines = parse(&mut Parser::new(true), EXPORT);
+        assert_eq!(lines.len(), 4);
+        assert!(lines[0]
+            .0
+            .ends_with(" init.scope: Started nginx.service - A high performance web server."));
+        assert_eq!(lines[0].1, None);
+        // Without a unit the identifier is shown
+        assert!(lines[1].0.ends_with(" backup.sh: rsync failed:"));
+        assert_eq!(lines[1].1, Some("31"));
+        // The second line of the message is indented below the first
+        let indent = lines[1].0.len() - "rsync failed:".len();
+        assert_eq!(
+            lines[2].0,
+            " ".repeat(indent) + "\tNo space left on device \x1b[1m(28)\x1b[0m"
+        );
+        assert_eq!(lines[2].1, Some("31"));
+        assert!(lines[3]
+            .0
+            .ends_with(" cron.service: (root) INFO (No MTA installed, discarding output)"));
+        assert_eq!(lines[3].1, Some("33"));
+    }
+
+    #[test]
+    fn entries_split_across_chunks() {
+        let expected = parse(&mut Parser::new(true), EXPORT);
+        for size in 1..=20 {
+            let mut parser = Parser::new(true);
+            let lines: Vec<_> = EXPORT
+                .chunks(size)
+                .flat_map(|chunk| parse(&mut parser, chunk))
+                .collect();
+            assert_eq!(lines, expected, "chunks of {size} bytes");
+        }
+    }
+
+    #[test]
+    fn resync_from_middle_of_entry() {
+        let expected = parse(&mut Parser::new(true), EXPORT);
+        let middle = EXPORT.windows(9).position(|w| w == b"_PID=1\n_C").unwrap();
+        let lines = parse(&mut Parser::new(false), &EXPORT[middle..]);
+        assert_eq!(lines, expected[1..]);
+        // Also when the empty line after the entry is split
+        let end = EXPORT.windows(2).position(|w| w == b"\n\n").unwrap();
+        let mut parser = Parser::new(false);
+        let mut lines = parse(&mut parser, &EXPORT[middle..end + 1]);
+        lines.extend(parse(&mut parser, &EXPORT[end + 1..]));
+        assert_eq!(lines, expected[1..]);
+    }
+
+    #[test]
+    fn resync_after_empty_line_in_binary_field() {
+        let expected = parse(&mut Parser::new(true), EXPORT);
+        let value = b"first\n\nsecond para";
+        let mut data = b"MESSAGE\n".to_vec();
+        data.extend_from_slice(&(value.len() as u64).to_le_bytes());
+        data.extend_from_slice(value);
+        data.extend_from_slice(b"\n\n");
+        let entry = data.len();
+        data.extend_from_slice(EXPORT);
+        // The empty line in the value is taken for the end of an entry
+        let mut parser = Parser::new(false);
+        let mut lines = parse(&mut parser, &data[5..entry]);
+        lines.extend(parse(&mut parser, &data[entry..]));
+        assert_eq!(lines, expected);
+        assert!(parser.pending.len() <= 1);
+
+        // A size that is read from text
+        let mut data = b"MESSAGE\nnot a size\n\n".to_vec();
+        data.extend_from_slice(EXPORT);
+        let lines = parse(&mut Parser::new(true), &data);
+        assert_eq!(lines, expected);
+    }
+
+    #[test]
+    fn finish_without_empty_line() {
+        let mut parser = Parser::new(true);
+        let lines = parse(&mut parser, &EXPORT[..EXPORT.len() - 1]);
+        assert_eq!(lines.len(), 3);
+        let mut rest = Vec::new();
+        parser.finish(&mut rest);
+        assert_eq!(rest.len(), 1);
+        assert!(rest[0]
+            .0
+            .ends_with(" cron.service: (root) INFO (No MTA installed, discarding output)"));
+        // Nothing is left for another call
+        parser.finish(&mut rest);
+        assert_eq!(rest.len(), 1);
+    }
+}
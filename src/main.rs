@@ -1,16 +1,32 @@
-use chrono::{DateTime, Local};
-use clap::Parser;
+use clap::{builder::RangedU64ValueParser, Parser, ValueEnum};
 use notify::{recommended_watcher, Event, RecursiveMode, Watcher};
-use std::sync::mpsc::channel;
-use std::{
-    fs::File,
-    io::{self, stdout, Read, Seek, SeekFrom, Write},
-    path::Path,
-};
-use unicode_width::{UnicodeWidthChar, UnicodeWidthStr};
-
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{channel, RecvTimeoutError, Sender};
+use std::time::Instant;
+
+mod command;
+mod compress;
+mod decode;
+mod escape;
+mod file_id;
+mod filter;
+mod highlight;
+mod input;
+mod journal;
 mod noecho;
+mod resize;
+mod source;
+mod timestamp;
+mod viewer;
+mod width;
+use decode::Encoding;
+use escape::Escapes;
+use highlight::{parse_style, Rule};
+use input::Key;
 use noecho::NoEcho;
+use source::{expand_globs, parse_size, SourceOptions, Start};
+use timestamp::parse_format;
+use viewer::{split_panes, CursorInfo, Viewer};
 
 type Result<T> = std::result::Result<T, Box<dyn std::error::Error>>;
 
@@ -72,265 +88,347 @@ impl Drop for HideCursor {
 
 #[derive(Parser)]
 struct Commandline {
-    /// File to view
-    file: String,
+    /// Files to view, glob patterns are expanded, `-` is standard input
+    #[arg(required_unless_present = "command")]
+    files: Vec<String>,
+
+    /// Run a command and view its standard output and standard error
+    /// instead of files
+    #[arg(last = true, value_name = "COMMAND", conflicts_with_all = ["files", "split"])]
+    command: Vec<String>,
 
     /// Show timestamps when a line is printed
     #[arg(short, long, default_value_t = false)]
     timestamps: bool,
 
+    /// Show the time found in each line instead of when it was read,
+    /// implies --timestamps. RFC 3339, syslog, Apache common log format,
+    /// and epoch seconds or milliseconds at the start of the line are
+    /// recognized
+    #[arg(long, default_value_t = false)]
+    line_time: bool,
+
+    /// Format of the times in the lines, using the syntax of strftime,
+    /// implies --line-time
+    #[arg(long, value_name = "FORMAT", value_parser = parse_format)]
+    time_format: Option<String>,
+
     /// Clear the scrollback buffer of the terminal when the file is truncated
     #[arg(short, long, default_value_t = false)]
     discard_old: bool,
-}
 
-struct CursorInfo {
-    term_lines: usize,
-    term_cols: usize,
-    cursor_line: usize,
-    cursor_col: usize,
-    save_line: usize,
-    save_col: usize,
-}
+    /// Follow the file by name, reopening it when it is rotated
+    #[arg(short = 'F', long, default_value_t = false)]
+    follow_name: bool,
 
-impl CursorInfo {
-    fn new() -> Self {
-        let (term_cols, mut term_lines) = term_size::dimensions().unwrap();
-        // Reserve one line for the status bar
-        term_lines -= 1;
-        Self {
-            term_lines,
-            term_cols,
-            cursor_line: 0,
-            cursor_col: 0,
-            save_line: 0,
-            save_col: 0,
-        }
-    }
+    /// Wait for the file to be created if it does not exist, implies --follow-name
+    #[arg(short, long, default_value_t = false)]
+    wait: bool,
+
+    /// Show each file in its own pane
+    #[arg(short, long, value_enum)]
+    split: Option<Split>,
+
+    /// Start with the last N lines, or at line N with +N
+    #[arg(short = 'n', long, value_name = "[+]N", value_parser = Start::parse_lines)]
+    lines: Option<Start>,
+
+    /// Start with the last N bytes, or at byte N with +N
+    #[arg(short = 'c', long, value_name = "[+]N", value_parser = Start::parse_bytes, conflicts_with = "lines")]
+    bytes: Option<Start>,
+
+    /// When more than SIZE bytes are added at once, skip to the last SIZE
+    /// bytes instead of showing everything (K, M, and G suffixes are allowed)
+    #[arg(long, value_name = "SIZE", value_parser = parse_size)]
+    skip_ahead: Option<u64>,
+
+    /// Character encoding of the files
+    #[arg(short, long, value_enum, default_value_t = Encoding::Utf8)]
+    encoding: Encoding,
+
+    /// How lines that are wider than the terminal are shown
+    #[arg(long, value_enum, default_value_t = Wrap::Char)]
+    wrap: Wrap,
+
+    /// Number of cells between tab stops
+    #[arg(long, value_name = "CELLS", default_value_t = 8, value_parser = RangedU64ValueParser::<usize>::new().range(1..))]
+    tab_width: usize,
+
+    /// Show an incomplete line after waiting MS milliseconds for the rest,
+    /// it is replaced once the rest arrives
+    #[arg(long, value_name = "MS")]
+    partial_timeout: Option<u64>,
+
+    /// Start a line over at a carriage return that is not part of a CRLF
+    /// line ending, so only the last version of progress bars is shown
+    #[arg(short = 'r', long, default_value_t = false)]
+    carriage_return: bool,
+
+    /// Read the journal export format, as written by `journalctl -o
+    /// export`, and show each entry as a line colored by its priority
+    #[arg(long, default_value_t = false)]
+    journal: bool,
+
+    /// Number of lines kept for scrolling back
+    #[arg(short, long, default_value_t = 10000)]
+    buffer: usize,
+
+    /// Only show lines matching this regular expression, can be given
+    /// multiple times to show lines matching any of them
+    #[arg(short, long, value_name = "PATTERN")]
+    grep: Vec<String>,
+
+    /// Hide lines matching this regular expression, can be given multiple
+    /// times
+    #[arg(short = 'v', long, value_name = "PATTERN")]
+    exclude: Vec<String>,
+
+    /// Color text matching PATTERN, STYLE is a comma separated list of
+    /// style names (bold, dim, italic, underline, reverse, red, bright-red,
+    /// on-red, ...) or SGR parameters
+    #[arg(short = 'H', long, value_name = "PATTERN=STYLE", value_parser = Rule::parse)]
+    highlight: Vec<Rule>,
+
+    /// Color the standard error of the command, STYLE is given like for
+    /// --highlight
+    #[arg(long, value_name = "STYLE", value_parser = parse_style)]
+    stderr_style: Option<String>,
+
+    /// Which escape sequences from the files are passed to the terminal,
+    /// the others are shown as text
+    #[arg(long, value_enum, default_value_t = Escapes::Sgr)]
+    escapes: Escapes,
+
+    /// Disable the built-in highlighting of log levels, IP addresses, and
+    /// UUIDs, for files that are already colored
+    #[arg(long, default_value_t = false)]
+    no_highlight: bool,
+}
 
-    fn newline(&mut self) {
-        if self.cursor_line != self.term_lines {
-            self.cursor_line += 1;
+impl Commandline {
+    /// How the files or the output of the command are read.
+    fn source_options(&self) -> SourceOptions {
+        SourceOptions {
+            wait: self.wait,
+            start: self.lines.or(self.bytes).unwrap_or(Start::Beginning),
+            skip_ahead: self.skip_ahead,
+            encoding: self.encoding,
+            carriage_return: self.carriage_return,
+            journal: self.journal,
         }
-        self.cursor_col = 0;
-    }
-
-    fn add(&mut self, n: usize) {
-        self.cursor_col += n;
-    }
-
-    fn save(&mut self) {
-        self.save_line = self.cursor_line;
-        self.save_col = self.cursor_col;
     }
+}
 
-    fn restore(&mut self) {
-        self.cursor_line = self.save_line;
-        self.cursor_col = self.save_col;
-        goto(self.cursor_line, self.cursor_col);
-    }
+#[derive(Clone, Copy, ValueEnum)]
+enum Split {
+    /// Panes are stacked on top of each other
+    Horizontal,
+    /// Panes are next to each other
+    Vertical,
+}
 
-    fn fits(&self, cells: usize) -> bool {
-        self.cursor_col + cells < self.term_cols
-    }
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum Wrap {
+    /// Lines are broken at the last character that fits
+    Char,
+    /// Lines are broken after whitespace when possible
+    Word,
+    /// Lines are cut off and can be scrolled horizontally
+    None,
+}
 
-    fn clear(&mut self) {
-        self.cursor_line = 0;
-        self.cursor_col = 0;
+/// Starts reading the streams of a viewer that are not read yet, which
+/// can't be watched.
+fn spawn_readers(viewer: &mut Viewer, tx: &Sender<Message>) {
+    for source in viewer.sources.iter_mut() {
+        let stream_tx = tx.clone();
+        source.spawn_reader(move || {
+            stream_tx.send(Message::Stream).ok();
+        });
     }
 }
 
-struct Viewer {
-    file_name: String,
-    timestamps: bool,
-    discard_old: bool,
-    line: Vec<char>,
-    file: File,
-    cursor: CursorInfo,
-    time: DateTime<Local>,
-    what_time: &'static str,
+/// Things the main loop reacts to.
+enum Message {
+    Watch(notify::Result<Event>),
+    /// Something can be read from a stream
+    Stream,
+    /// Reading stopped before everything was read
+    More,
+    Key(Key),
+    Resize,
+    Quit,
 }
 
-impl Viewer {
-    fn new(args: &Commandline) -> io::Result<Self> {
-        Ok(Self {
-            file_name: if let Ok(home_dir) = std::env::var("HOME") {
-                args.file.replace(&home_dir, "~")
-            } else {
-                args.file.clone()
-            },
-            timestamps: args.timestamps,
-            discard_old: args.discard_old,
-            line: Vec::new(),
-            file: File::open(&args.file)?,
-            cursor: CursorInfo::new(),
-            time: Local::now(),
-            what_time: "Started",
-        })
-    }
-
-    fn on_change(&mut self) {
-        let mut data = Vec::new();
-        let old_position = self.file.stream_position().unwrap();
-        if self.file.read_to_end(&mut data).is_ok() {
-            self.file.seek(SeekFrom::End(0)).unwrap();
-            let new_position = self.file.stream_position().unwrap();
-            if new_position == 0 && old_position != 0 {
-                self.truncate();
-            } else {
-                self.add_bytes(&data);
-            }
-        } else {
-            panic!("Read failed");
+fn run(cmdline: Commandline) -> Result<()> {
+    let files = expand_globs(&cmdline.files)?;
+    let mut viewers = if let Some(split) = cmdline.split {
+        let panes = split_panes(split, files.len());
+        files
+            .iter()
+            .zip(panes)
+            .map(|(file, pane)| Viewer::new(&cmdline, std::slice::from_ref(file), Some(pane)))
+            .collect::<Result<Vec<_>>>()?
+    } else {
+        vec![Viewer::new(&cmdline, &files, None)?]
+    };
+    let paths: Vec<PathBuf> = viewers
+        .iter()
+        .flat_map(|viewer| &viewer.sources)
+        .filter(|source| !source.is_stream())
+        .map(|source| source.path.clone())
+        .collect();
+    let follow_name = cmdline.follow_name || cmdline.wait;
+    // Clear screen initially so we know the cursor position,
+    // instead of bothering to read it using escape sequences.
+    clear_screen(false);
+    let _hide_cursor = HideCursor::begin();
+    let mut more = false;
+    for (i, viewer) in viewers.iter_mut().enumerate() {
+        // Draw the status bar even if the viewed files are initially empty,
+        // keys go to the first pane.
+        viewer.draw_border();
+        viewer.set_focused(i == 0);
+        // Read initial content
+        for source in 0..viewer.sources.len() {
+            more |= viewer.on_change(source);
         }
     }
-
-    fn truncate(&mut self) {
-        self.time = Local::now();
-        self.what_time = "Created";
-        clear_screen(self.discard_old);
-        self.line.clear();
-        self.cursor.clear();
-        self.print_header(true);
-        stdout().flush().ok();
+    // Watch for changes
+    let (tx, rx) = channel();
+    // Whether a `Message::More` is waiting in the channel
+    let mut more_sent = more;
+    if more {
+        tx.send(Message::More).ok();
     }
-
-    fn add_bytes(&mut self, data: &[u8]) {
-        for c in String::from_utf8_lossy(data).chars() {
-            if c == '\n' {
-                self.print_line();
-            } else if c == '\r' {
-                continue;
-            } else {
-                self.line.push(c);
-            }
+    let watch_tx = tx.clone();
+    let mut watcher = recommended_watcher(move |event_or_error| {
+        watch_tx.send(Message::Watch(event_or_error)).ok();
+    })?;
+    let terminal = input::open_terminal()?;
+    let _no_echo = NoEcho::begin(terminal.try_clone()?);
+    if follow_name {
+        // Watch the directories so we still get events after the files have
+        // been renamed or removed.
+        let mut directories: Vec<&Path> = paths.iter().filter_map(|path| path.parent()).collect();
+        directories.sort();
+        directories.dedup();
+        for directory in directories {
+            watcher.watch(directory, RecursiveMode::NonRecursive)?;
         }
-    }
-
-    fn print_escape(&self, mut i: usize) -> usize {
-        let start = i;
-        // '\x1b['
-        i += 2;
-        // Consume all following numbers and semicolons
-        while i < self.line.len() {
-            let c = self.line[i];
-            if !(c.is_ascii_digit() || c == ';') {
-                break;
-            }
-            i += 1;
+    } else {
+        for path in &paths {
+            watcher.watch(path, RecursiveMode::NonRecursive)?;
         }
-        // Terminating character
-        i += 1;
-        // Collect and print at once since most terminals don't let you print
-        // escape sequences character by character.
-        let seq: String = self.line[start..i].iter().collect();
-        print!("{}", seq);
-        i
     }
-
-    fn newline(&mut self) {
-        println!("\x1b[K");
-        self.cursor.newline();
+    let key_tx = tx.clone();
+    input::spawn(terminal, move |key| {
+        key_tx.send(Message::Key(key)).ok();
+    });
+    for viewer in viewers.iter_mut() {
+        spawn_readers(viewer, &tx);
     }
-
-    fn print_line(&mut self) {
-        let timestamp_size;
-        if self.timestamps {
-            let now = Local::now();
-            let timestamp = now.format("%H:%M:%S ").to_string();
-            print!("\x1b[2m{}\x1b[0m", timestamp);
-            timestamp_size = timestamp.width();
-            self.cursor.add(timestamp_size);
-        } else {
-            timestamp_size = 0;
-        }
-        let timestamp_space = repeat_ascii(' ', timestamp_size);
-        let mut i = 0;
-        while i < self.line.len() {
-            let c = self.line[i];
-            if c == '\x1b' {
-                i = self.print_escape(i);
-                continue;
+    let resize_tx = tx.clone();
+    resize::spawn(move || {
+        resize_tx.send(Message::Resize).ok();
+    })?;
+    // Run until SIGINT, SIGTERM, or SIGHUP
+    let quit_tx = tx.clone();
+    ctrlc::set_handler(move || {
+        quit_tx.send(Message::Quit).ok();
+    })?;
+    let mut focus = 0;
+    loop {
+        // Wake up when an incomplete line should be shown or the status bar
+        // changes
+        let deadline = viewers.iter().filter_map(Viewer::deadline).min();
+        let message = match deadline {
+            Some(deadline) => {
+                match rx.recv_timeout(deadline.saturating_duration_since(Instant::now())) {
+                    Ok(message) => message,
+                    Err(RecvTimeoutError::Timeout) => {
+                        for viewer in viewers.iter_mut() {
+                            viewer.on_timeout();
+                        }
+                        continue;
+                    }
+                    Err(RecvTimeoutError::Disconnected) => break,
+                }
             }
-            let w = c.width().unwrap_or(1);
-            if !self.cursor.fits(w) {
-                self.newline();
-                print!("{}", timestamp_space);
-                self.cursor.add(timestamp_size);
+            None => match rx.recv() {
+                Ok(message) => message,
+                Err(_) => break,
+            },
+        };
+        let mut more = false;
+        match message {
+            Message::Watch(Ok(event)) => {
+                use notify::{
+                    event::ModifyKind::Name,
+                    EventKind::{Create, Modify, Remove},
+                };
+                for viewer in viewers.iter_mut() {
+                    for source in 0..viewer.sources.len() {
+                        // Ignore events for other files in the same directory
+                        if !event.paths.contains(&viewer.sources[source].path) {
+                            continue;
+                        }
+                        more |= match event.kind {
+                            Create(_) | Modify(Name(_)) if follow_name => viewer.on_rename(source),
+                            Remove(_) if follow_name => viewer.on_remove(source),
+                            Modify(_) => viewer.on_change(source),
+                            _ => false,
+                        };
+                    }
+                }
             }
-            print!("{}", c);
-            self.cursor.add(w);
-            i += 1;
-        }
-        self.newline();
-        self.line.clear();
-        self.print_header(false);
-        stdout().flush().ok();
-    }
-
-    fn print_header(&mut self, truncated: bool) {
-        self.cursor.save();
-        print!("\x1b[7m");
-
-        goto(self.cursor.term_lines, 0);
-        print!("{}", repeat_ascii(' ', self.cursor.term_cols));
-
-        goto(self.cursor.term_lines, 1);
-        print!("Viewing \x1b[1m{}\x1b[22m", self.file_name);
-
-        if truncated {
-            print!("   File truncated");
-        }
-
-        let time = format!("{} at {}", self.what_time, self.time.format("%H:%M:%S"));
-        goto(
-            self.cursor.term_lines,
-            self.cursor.term_cols - time.len() - 1,
-        );
-        print!("{}", time);
-
-        print!("\x1b[27m");
-        self.cursor.restore();
-    }
-}
-
-fn run(cmdline: Commandline) -> Result<()> {
-    // Clear screen initially so we know the cursor position,
-    // instead of bothering to read it using escape sequences.
-    clear_screen(false);
-    let mut viewer = Viewer::new(&cmdline)?;
-    let _hide_cursor = HideCursor::begin();
-    // This `print_line` causes a update even
-    // if the viewed file is initially empty
-    viewer.print_line();
-    stdout().flush().ok();
-    // Read initial content
-    viewer.on_change();
-    // Watch for changes
-    let mut watcher =
-        recommended_watcher(
-            move |event_or_error: notify::Result<Event>| match event_or_error {
-                Ok(event) => {
-                    use notify::EventKind::Modify;
-                    if matches!(event.kind, Modify(_)) {
-                        viewer.on_change();
+            Message::Watch(Err(error)) => return Err(format!("watch error: {error}").into()),
+            Message::Stream => {
+                for viewer in viewers.iter_mut() {
+                    for source in 0..viewer.sources.len() {
+                        if viewer.sources[source].is_stream() {
+                            more |= viewer.on_change(source);
+                        }
                     }
                 }
-                Err(error) => {
-                    eprintln!("watch error: {error}");
-                    alternative_screen_buffer(false);
-                    std::process::exit(1);
+            }
+            Message::More => {
+                more_sent = false;
+                for viewer in viewers.iter_mut() {
+                    for source in 0..viewer.sources.len() {
+                        more |= viewer.on_change(source);
+                    }
                 }
-            },
-        )?;
-    let _no_echo = NoEcho::begin();
-    watcher.watch(Path::new(&cmdline.file), RecursiveMode::NonRecursive)?;
-    // Run until SIGINT, SIGTERM, or SIGHUP
-    let (tx, rx) = channel();
-    ctrlc::set_handler(move || {
-        tx.send(()).ok();
-    })?;
-    rx.recv()?;
+            }
+            Message::Key(Key::Tab) if viewers.len() > 1 => {
+                viewers[focus].set_focused(false);
+                focus = (focus + 1) % viewers.len();
+                viewers[focus].set_focused(true);
+            }
+            Message::Key(key) => {
+                viewers[focus].on_key(key);
+                // A restarted command has new streams
+                spawn_readers(&mut viewers[focus], &tx);
+            }
+            Message::Resize => {
+                clear_screen(false);
+                if let Some(split) = cmdline.split {
+                    let panes = split_panes(split, viewers.len());
+                    for (viewer, pane) in viewers.iter_mut().zip(panes) {
+                        viewer.resize(pane);
+                    }
+                } else {
+                    viewers[0].resize(CursorInfo::new());
+                }
+            }
+            Message::Quit => break,
+        }
+        // Continue after the events that arrived in the meantime
+        if more && !more_sent {
+            tx.send(Message::More).ok();
+            more_sent = true;
+        }
+    }
     Ok(())
 }
 
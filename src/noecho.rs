@@ -1,71 +1,81 @@
+use std::fs::File;
+
 #[cfg(target_family = "unix")]
 mod detail {
     use std::os::unix::io::AsRawFd;
-    use std::{
-        io::{stdin, Result},
-        mem::MaybeUninit,
-    };
-    use termios::{tcgetattr, tcsetattr, Termios, ECHO, TCSAFLUSH};
+    use std::{fs::File, io::Result, mem::MaybeUninit};
+    use termios::{tcgetattr, tcsetattr, Termios, ECHO, ICANON, TCSAFLUSH, VMIN, VTIME};
 
     pub type ConsoleMode = Termios;
 
-    pub fn disable_echo() -> Result<ConsoleMode> {
-        let fd = stdin().as_raw_fd();
+    pub fn disable_echo(terminal: &File) -> Result<ConsoleMode> {
+        let fd = terminal.as_raw_fd();
         let mut old = unsafe { MaybeUninit::zeroed().assume_init() };
         tcgetattr(fd, &mut old)?;
         let mut new = old;
-        new.c_lflag &= !ECHO;
+        // Also disable line buffering so keys can be read immediately
+        new.c_lflag &= !(ECHO | ICANON);
+        new.c_cc[VMIN] = 1;
+        new.c_cc[VTIME] = 0;
         tcsetattr(fd, TCSAFLUSH, &new)?;
         Ok(old)
     }
 
-    pub fn restore(mode: ConsoleMode) {
-        tcsetattr(stdin().as_raw_fd(), TCSAFLUSH, &mode).ok();
+    pub fn restore(terminal: &File, mode: ConsoleMode) {
+        tcsetattr(terminal.as_raw_fd(), TCSAFLUSH, &mode).ok();
     }
 }
 
 #[cfg(target_family = "windows")]
 mod detail {
+    use std::{fs::File, os::windows::io::AsRawHandle};
     use windows::{
         core::Result,
-        Win32::System::Console::{
-            GetConsoleMode, GetStdHandle, SetConsoleMode, CONSOLE_MODE, ENABLE_ECHO_INPUT,
-            STD_INPUT_HANDLE,
+        Win32::{
+            Foundation::HANDLE,
+            System::Console::{
+                GetConsoleMode, SetConsoleMode, CONSOLE_MODE, ENABLE_ECHO_INPUT, ENABLE_LINE_INPUT,
+                ENABLE_VIRTUAL_TERMINAL_INPUT,
+            },
         },
     };
 
     pub type ConsoleMode = CONSOLE_MODE;
 
-    pub fn disable_echo() -> Result<ConsoleMode> {
+    pub fn disable_echo(terminal: &File) -> Result<ConsoleMode> {
         unsafe {
-            let handle = GetStdHandle(STD_INPUT_HANDLE)?;
+            let handle = HANDLE(terminal.as_raw_handle() as isize);
             let mut old = CONSOLE_MODE(0);
             GetConsoleMode(handle, &mut old).ok()?;
             let mut new = old;
-            new &= !ENABLE_ECHO_INPUT;
+            // Also disable line buffering so keys can be read immediately,
+            // and get them as escape sequences like on unix.
+            new &= !(ENABLE_ECHO_INPUT | ENABLE_LINE_INPUT);
+            new |= ENABLE_VIRTUAL_TERMINAL_INPUT;
             SetConsoleMode(handle, new).ok()?;
             Ok(old)
         }
     }
 
-    pub fn restore(mode: ConsoleMode) {
+    pub fn restore(terminal: &File, mode: ConsoleMode) {
         unsafe {
-            if let Ok(handle) = GetStdHandle(STD_INPUT_HANDLE) {
-                SetConsoleMode(handle, mode);
-            }
+            SetConsoleMode(HANDLE(terminal.as_raw_handle() as isize), mode);
         }
     }
 }
 
 pub struct NoEcho {
+    terminal: File,
     old_mode: Option<detail::ConsoleMode>,
 }
 
 impl NoEcho {
-    /// Disable input echoing until the returned value is dropped.
-    pub fn begin() -> Self {
+    /// Disable input echoing and line buffering of the terminal until the
+    /// returned value is dropped.
+    pub fn begin(terminal: File) -> Self {
         Self {
-            old_mode: detail::disable_echo().ok(),
+            old_mode: detail::disable_echo(&terminal).ok(),
+            terminal,
         }
     }
 }
@@ -73,7 +83,7 @@ impl NoEcho {
 impl Drop for NoEcho {
     fn drop(&mut self) {
         if let Some(old_mode) = self.old_mode.take() {
-            detail::restore(old_mode);
+            detail::restore(&self.terminal, old_mode);
         }
     }
 }
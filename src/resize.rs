@@ -0,0 +1,41 @@
+#[cfg(target_family = "unix")]
+mod detail {
+    use signal_hook::{consts::SIGWINCH, iterator::Signals};
+    use std::thread;
+
+    pub fn spawn(mut on_resize: impl FnMut() + Send + 'static) -> std::io::Result<()> {
+        let mut signals = Signals::new([SIGWINCH])?;
+        thread::spawn(move || {
+            for _ in signals.forever() {
+                on_resize();
+            }
+        });
+        Ok(())
+    }
+}
+
+#[cfg(target_family = "windows")]
+mod detail {
+    use std::{thread, time::Duration};
+
+    // The console only reports size changes as input records, which we don't
+    // read since input is read as escape sequences, so just poll the size.
+    pub fn spawn(mut on_resize: impl FnMut() + Send + 'static) -> std::io::Result<()> {
+        let mut size = term_size::dimensions();
+        thread::spawn(move || loop {
+            thread::sleep(Duration::from_millis(250));
+            let new_size = term_size::dimensions();
+            if new_size != size {
+                size = new_size;
+                on_resize();
+            }
+        });
+        Ok(())
+    }
+}
+
+/// Calls `on_resize` on a separate thread whenever the size of the terminal
+/// changes.
+pub fn spawn(on_resize: impl FnMut() + Send + 'static) -> std::io::Result<()> {
+    detail::spawn(on_resize)
+}
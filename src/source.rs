@@ -0,0 +1,1303 @@
+use crate::compress::Decompressor;
+use crate::decode::{Decoder, Encoding};
+use crate::file_id::{file_id, FileId};
+use crate::journal;
+use std::{
+    fs::File,
+    io::{self, Read, Seek, SeekFrom},
+    path::{Path, PathBuf},
+    sync::mpsc::{sync_channel, Receiver, TryRecvError},
+    thread,
+    time::Instant,
+};
+
+/// Something that happened to a source, in the order it happened.
+pub enum Update {
+    /// A complete line was read
+    Line(String),
+    /// A journal entry was read, formatted as a line, with the SGR
+    /// parameters for its priority
+    Entry(String, Option<&'static str>),
+    /// The file was truncated, the content read before is gone
+    Truncated,
+    /// A new file was created under the name of the old one
+    Rotated,
+    /// The file was created while waiting for it
+    Created,
+    /// The file was removed and is now waited for
+    Removed,
+    /// A stream was closed by its writer
+    Ended,
+    /// Reading stopped because of an error, such as corrupt compressed data
+    Error(String),
+    /// This many bytes were skipped because too much was added at once
+    Skipped(u64),
+}
+
+/// Where to start reading a file that exists when the program starts.
+#[derive(Clone, Copy)]
+pub enum Start {
+    Beginning,
+    LastLines(u64),
+    /// Line number, starting at 1
+    FromLine(u64),
+    LastBytes(u64),
+    /// Byte number, starting at 1
+    FromByte(u64),
+}
+
+impl Start {
+    /// Parses `N` for the last N lines or `+N` for starting at line N.
+    pub fn parse_lines(arg: &str) -> Result<Self, String> {
+        Self::parse(arg, Self::LastLines, Self::FromLine)
+    }
+
+    /// Parses `N` for the last N bytes or `+N` for starting at byte N.
+    pub fn parse_bytes(arg: &str) -> Result<Self, String> {
+        Self::parse(arg, Self::LastBytes, Self::FromByte)
+    }
+
+    fn parse(arg: &str, last: fn(u64) -> Self, from: fn(u64) -> Self) -> Result<Self, String> {
+        let (make, number) = match arg.strip_prefix('+') {
+            Some(number) => (from, number),
+            None => (last, arg),
+        };
+        number
+            .parse()
+            .map(make)
+            .map_err(|error| format!("{arg}: {error}"))
+    }
+}
+
+/// How a source is read, given on the command line.
+#[derive(Clone, Copy)]
+pub struct SourceOptions {
+    /// Whether a file that does not exist is waited for
+    pub wait: bool,
+    /// Where to start reading a file that exists when the program starts
+    pub start: Start,
+    /// If more than this many bytes are unread, skip to the last this many
+    pub skip_ahead: Option<u64>,
+    pub encoding: Encoding,
+    /// Whether a carriage return that is not followed by a newline starts
+    /// the line over
+    pub carriage_return: bool,
+    /// Whether the input is in the journal export format instead of lines
+    pub journal: bool,
+}
+
+/// Input that cannot be watched or seeked, such as standard input or a named
+/// pipe, it is read on a separate thread instead.
+enum Stream {
+    /// Waiting for the thread to be started
+    Idle(Input),
+    /// Receives what was read, or the error that stopped reading
+    Reading(Receiver<io::Result<Vec<u8>>>),
+    Ended,
+}
+
+/// What a stream reads from.
+enum Input {
+    Stdin,
+    /// Path of a named pipe, which is opened by the thread
+    Pipe(PathBuf),
+    /// Output of a command
+    Reader(Box<dyn Read + Send>),
+}
+
+/// What happens once the rest of the current file was read.
+enum Closing {
+    /// Continue with the new file under the same name
+    Rotated(File, FileId),
+    /// Wait for the file to be created again
+    Removed,
+}
+
+/// A file that is being followed.
+pub struct Source {
+    /// Name of the file for displaying
+    pub name: String,
+    /// Absolute path of the file
+    pub path: PathBuf,
+    /// Incomplete line that was read so far
+    line: String,
+    /// When the first part of the incomplete line was read
+    line_since: Option<Instant>,
+    /// Whether a carriage return that is not followed by a newline starts
+    /// the line over
+    carriage_return: bool,
+    /// Whether the last character read was a carriage return
+    after_cr: bool,
+    decoder: Decoder,
+    encoding: Encoding,
+    /// Set when reading the journal export format instead of lines
+    journal: Option<journal::Parser>,
+    /// Set when the file is compressed, reads go through it
+    decompressor: Option<Decompressor>,
+    file: Option<File>,
+    file_id: Option<FileId>,
+    /// Set when the file was replaced or removed but not read to its end yet
+    closing: Option<Closing>,
+    stream: Option<Stream>,
+    /// If more than this many bytes are unread, skip to the last this many
+    skip_ahead: Option<u64>,
+    /// SGR parameters that every line starts with
+    pub tint: Option<String>,
+}
+
+impl Source {
+    /// Opens the file at `file_name`, if `options.wait` is true the file
+    /// does not need to exist. If it exists reading starts at
+    /// `options.start`, files that are created later are always read from
+    /// the beginning.
+    /// Standard input (`-`) and named pipes are always read from the
+    /// beginning, once `spawn_reader` is called.
+    pub fn open(file_name: &str, options: SourceOptions) -> io::Result<Self> {
+        if file_name == "-" {
+            return Ok(Self::stream("standard input", Input::Stdin, options));
+        }
+        if is_fifo(file_name) {
+            let input = Input::Pipe(PathBuf::from(file_name));
+            return Ok(Self::stream(file_name, input, options));
+        }
+        let SourceOptions {
+            wait,
+            start,
+            encoding,
+            journal,
+            ..
+        } = options;
+        let mut decoder = Decoder::new(encoding);
+        let mut parser = journal.then(|| journal::Parser::new(true));
+        let mut decompressor = None;
+        let file = match File::open(file_name) {
+            Ok(mut file) => {
+                decompressor = Decompressor::detect(Path::new(file_name), &file)?;
+                let position = match &mut decompressor {
+                    Some(decompressor) => {
+                        start_decompressed(decompressor, start, encoding, journal)?
+                    }
+                    None => seek_start(&mut file, start, encoding, journal)?,
+                };
+                decoder.reset(position == 0);
+                if let Some(parser) = &mut parser {
+                    // Entries that are counted start right after an empty line
+                    let at_entry = matches!(start, Start::LastLines(_) | Start::FromLine(_));
+                    parser.reset(position == 0 || at_entry);
+                }
+                Some(file)
+            }
+            Err(error) if wait && error.kind() == io::ErrorKind::NotFound => None,
+            Err(error) => return Err(error),
+        };
+        let file_id = match &file {
+            Some(file) => Some(file_id(file)?),
+            None => None,
+        };
+        Ok(Self {
+            name: if let Ok(home_dir) = std::env::var("HOME") {
+                file_name.replace(&home_dir, "~")
+            } else {
+                file_name.to_string()
+            },
+            path: watched_path(file_name)?,
+            line: String::new(),
+            line_since: None,
+            carriage_return: options.carriage_return,
+            after_cr: false,
+            decoder,
+            encoding,
+            journal: parser,
+            decompressor,
+            file,
+            file_id,
+            closing: None,
+            stream: None,
+            skip_ahead: options.skip_ahead,
+            tint: None,
+        })
+    }
+
+    /// Creates a source that reads from `reader` once `spawn_reader` is
+    /// called, such as the output of a command.
+    pub fn from_reader(name: &str, reader: Box<dyn Read + Send>, options: SourceOptions) -> Self {
+        Self::stream(name, Input::Reader(reader), options)
+    }
+
+    /// Streams are always read from the beginning and never skip ahead.
+    fn stream(name: &str, input: Input, options: SourceOptions) -> Self {
+        Self {
+            name: name.to_string(),
+            path: PathBuf::from(name),
+            line: String::new(),
+            line_since: None,
+            carriage_return: options.carriage_return,
+            after_cr: false,
+            decoder: Decoder::new(options.encoding),
+            encoding: options.encoding,
+            journal: options.journal.then(|| journal::Parser::new(true)),
+            decompressor: None,
+            file: None,
+            file_id: None,
+            closing: None,
+            stream: Some(Stream::Idle(input)),
+            skip_ahead: None,
+            tint: None,
+        }
+    }
+
+    /// Continues with another reader, such as the output of a restarted
+    /// command. What was not read from the old one is dropped.
+    pub fn replace_reader(&mut self, reader: Box<dyn Read + Send>) {
+        self.stream = Some(Stream::Idle(Input::Reader(reader)));
+        self.clear_line();
+        self.reset_decoding();
+    }
+
+    /// Returns whether the file is currently open, as opposed to being
+    /// waited for. Streams are never waited for.
+    pub fn is_open(&self) -> bool {
+        self.file.is_some() || self.stream.is_some()
+    }
+
+    /// Returns whether this is standard input, a named pipe, or the output of
+    /// a command, which are not watched.
+    pub fn is_stream(&self) -> bool {
+        self.stream.is_some()
+    }
+
+    /// Starts reading a stream on a separate thread, `on_data` is called
+    /// whenever something can be read with `read_chunk`.
+    pub fn spawn_reader(&mut self, mut on_data: impl FnMut() + Send + 'static) {
+        let input = match self.stream.take() {
+            Some(Stream::Idle(input)) => input,
+            stream => {
+                self.stream = stream;
+                return;
+            }
+        };
+        // Only a few chunks are buffered, the writer has to wait for the rest
+        let (sender, receiver) = sync_channel(16);
+        self.stream = Some(Stream::Reading(receiver));
+        thread::spawn(move || {
+            // Opening a named pipe waits for a writer
+            let mut reader: Box<dyn Read> = match input {
+                Input::Stdin => Box::new(io::stdin()),
+                Input::Pipe(path) => match File::open(path) {
+                    Ok(file) => Box::new(file),
+                    Err(error) => {
+                        sender.send(Err(error)).ok();
+                        drop(sender);
+                        on_data();
+                        return;
+                    }
+                },
+                Input::Reader(reader) => reader,
+            };
+            let mut buf = vec![0; CHUNK_SIZE as usize];
+            loop {
+                let size = match reader.read(&mut buf) {
+                    Ok(0) => break,
+                    Ok(size) => size,
+                    Err(error) => {
+                        sender.send(Err(error)).ok();
+                        break;
+                    }
+                };
+                if sender.send(Ok(buf[..size].to_vec())).is_err() {
+                    return;
+                }
+                on_data();
+            }
+            // Tell that the stream ended
+            drop(sender);
+            on_data();
+        });
+    }
+
+    /// Returns the incomplete line that was read so far and since when it
+    /// is waiting for the rest.
+    pub fn partial(&self) -> Option<(&str, Instant)> {
+        self.line_since.map(|since| (self.line.as_str(), since))
+    }
+
+    /// Reads up to one chunk of what was added to the file, returns whether
+    /// there may be more to read.
+    pub fn read_chunk(&mut self, updates: &mut Vec<Update>) -> bool {
+        if let Some(stream) = &self.stream {
+            let received = match stream {
+                Stream::Reading(receiver) => receiver.try_recv(),
+                _ => return false,
+            };
+            return match received {
+                Ok(Ok(data)) => {
+                    self.add_bytes(&data, updates);
+                    true
+                }
+                Ok(Err(error)) => {
+                    updates.push(Update::Error(error.to_string()));
+                    true
+                }
+                Err(TryRecvError::Empty) => false,
+                Err(TryRecvError::Disconnected) => {
+                    self.stream = Some(Stream::Ended);
+                    self.end_line(updates);
+                    updates.push(Update::Ended);
+                    true
+                }
+            };
+        }
+        let file = match &mut self.file {
+            Some(file) => file,
+            None => return false,
+        };
+        let position = file.stream_position().unwrap();
+        let length = file.metadata().map_or(position, |metadata| metadata.len());
+        if length < position {
+            file.seek(SeekFrom::Start(0)).unwrap();
+            self.clear_line();
+            self.reset_decoding();
+            updates.push(Update::Truncated);
+            return true;
+        }
+        // Positions in compressed files are not those of the lines
+        if let Some(skip_ahead) = self.skip_ahead.filter(|_| self.decompressor.is_none()) {
+            if length - position > skip_ahead {
+                let new_position = next_line_position(file, length - skip_ahead, self.encoding);
+                file.seek(SeekFrom::Start(new_position)).unwrap();
+                self.line.clear();
+                self.line_since = None;
+                self.after_cr = false;
+                self.decoder.reset(false);
+                if let Some(journal) = &mut self.journal {
+                    journal.reset(false);
+                }
+                updates.push(Update::Skipped(new_position - position));
+            }
+        }
+        let mut buf = vec![0; CHUNK_SIZE as usize];
+        let result = match &mut self.decompressor {
+            Some(decompressor) => decompressor.read(&mut buf),
+            None => file.read(&mut buf),
+        };
+        match result {
+            Ok(0) => match self.closing.take() {
+                Some(closing) => {
+                    self.close(closing, updates);
+                    true
+                }
+                None => false,
+            },
+            Ok(size) => {
+                self.add_bytes(&buf[..size], updates);
+                true
+            }
+            // The decompressor stops at corrupt data
+            Err(error) if self.decompressor.is_some() => {
+                updates.push(Update::Error(error.to_string()));
+                true
+            }
+            Err(_) => panic!("Read failed"),
+        }
+    }
+
+    /// Reads everything that was added to the file.
+    #[cfg(test)]
+    fn read_all(&mut self, updates: &mut Vec<Update>) {
+        while self.read_chunk(updates) {}
+    }
+
+    /// Called when the name of the file was created or renamed.
+    /// If the name now refers to a different file it is opened, and read
+    /// once `read_chunk` has read the rest of the old file.
+    pub fn on_rename(&mut self, updates: &mut Vec<Update>) {
+        // If the name does not exist (yet) keep the old file, a rotation
+        // may rename the file before creating the new one.
+        let file = match File::open(&self.path) {
+            Ok(file) => file,
+            Err(_) => return,
+        };
+        let file_id = match file_id(&file) {
+            Ok(file_id) => file_id,
+            Err(_) => return,
+        };
+        if Some(file_id) == self.file_id {
+            return;
+        }
+        if let Some(Closing::Rotated(_, next_id)) = &self.closing {
+            if *next_id == file_id {
+                return;
+            }
+        }
+        if self.file.is_some() {
+            self.closing = Some(Closing::Rotated(file, file_id));
+        } else {
+            updates.push(Update::Created);
+            self.file = Some(file);
+            self.file_id = Some(file_id);
+            self.reset_decoding();
+        }
+    }
+
+    /// Called when the file was removed, starts waiting for it to be
+    /// created again once `read_chunk` has read the rest of it.
+    pub fn on_remove(&mut self, updates: &mut Vec<Update>) {
+        if self.path.exists() {
+            // Already replaced by a new file
+            self.on_rename(updates);
+            return;
+        }
+        if self.file.is_none() {
+            return;
+        }
+        self.closing = Some(Closing::Removed);
+    }
+
+    /// Closes the current file after its rest was read.
+    fn close(&mut self, closing: Closing, updates: &mut Vec<Update>) {
+        self.end_line(updates);
+        match closing {
+            Closing::Rotated(file, file_id) => {
+                self.file = Some(file);
+                self.file_id = Some(file_id);
+                self.reset_decoding();
+                updates.push(Update::Rotated);
+            }
+            Closing::Removed => {
+                self.file = None;
+                self.file_id = None;
+                updates.push(Update::Removed);
+            }
+        }
+    }
+
+    fn add_bytes(&mut self, data: &[u8], updates: &mut Vec<Update>) {
+        if let Some(journal) = &mut self.journal {
+            let mut entries = Vec::new();
+            journal.parse(data, &mut entries);
+            push_entries(entries, updates);
+            return;
+        }
+        let mut text = String::new();
+        self.decoder.decode(data, &mut text);
+        for c in text.chars() {
+            let after_cr = std::mem::replace(&mut self.after_cr, c == '\r');
+            if c == '\n' {
+                updates.push(Update::Line(std::mem::take(&mut self.line)));
+                self.line_since = None;
+            } else if c == '\r' {
+                continue;
+            } else {
+                if after_cr && self.carriage_return {
+                    // Like a terminal, start over but keep showing the line
+                    self.line.clear();
+                }
+                self.line.push(c);
+                self.line_since.get_or_insert_with(Instant::now);
+            }
+        }
+    }
+
+    /// Emits the incomplete line, for when nothing will be added to it.
+    fn end_line(&mut self, updates: &mut Vec<Update>) {
+        if let Some(journal) = &mut self.journal {
+            let mut entries = Vec::new();
+            journal.finish(&mut entries);
+            push_entries(entries, updates);
+        }
+        self.decoder.finish(&mut self.line);
+        if !self.line.is_empty() {
+            updates.push(Update::Line(std::mem::take(&mut self.line)));
+        }
+        self.line_since = None;
+        self.after_cr = false;
+    }
+
+    /// Forgets incomplete characters and entries, for when reading
+    /// continues at the start of a file, which may be compressed.
+    fn reset_decoding(&mut self) {
+        self.decompressor = match &self.file {
+            Some(file) => Decompressor::detect(&self.path, file).ok().flatten(),
+            None => None,
+        };
+        self.decoder.reset(true);
+        if let Some(journal) = &mut self.journal {
+            journal.reset(true);
+        }
+    }
+
+    fn clear_line(&mut self) {
+        self.line.clear();
+        self.line_since = None;
+        self.after_cr = false;
+    }
+}
+
+/// Passes journal entries on as updates.
+fn push_entries(entries: Vec<(String, Option<&'static str>)>, updates: &mut Vec<Update>) {
+    updates.extend(
+        entries
+            .into_iter()
+            .map(|(text, style)| Update::Entry(text, style)),
+    );
+}
+
+/// Moves to where reading should start and returns that position.
+/// In the journal export format lines are entries.
+fn seek_start(file: &mut File, start: Start, encoding: Encoding, journal: bool) -> io::Result<u64> {
+    let position = match start {
+        Start::Beginning => 0,
+        Start::LastLines(count) if journal => last_entries_position(file, count)?,
+        Start::LastLines(count) => last_lines_position(file, count, encoding)?,
+        Start::FromLine(entry) if journal => entry_position(file, entry)?,
+        Start::FromLine(line) => line_position(file, line, encoding)?,
+        Start::LastBytes(count) => file.seek(SeekFrom::End(0))?.saturating_sub(count),
+        // Past the end reading starts at the end, like with lines
+        Start::FromByte(byte) => byte.saturating_sub(1).min(file.seek(SeekFrom::End(0))?),
+    };
+    file.seek(SeekFrom::Start(encoding.align(position)))
+}
+
+pub const CHUNK_SIZE: u64 = 64 * 1024;
+
+/// Returns the position of the last `count` lines by reading backwards from
+/// the end in chunks, so only the end of large files needs to be read.
+fn last_lines_position(file: &mut File, count: u64, encoding: Encoding) -> io::Result<u64> {
+    let length = file.seek(SeekFrom::End(0))?;
+    if count == 0 {
+        return Ok(length);
+    }
+    let mut buf = vec![0; CHUNK_SIZE as usize];
+    let mut position = length;
+    let mut newlines = 0;
+    while position > 0 {
+        // Chunks start at multiples of their size, so both bytes of a
+        // UTF-16 character are in the same chunk
+        let start = (position - 1) / CHUNK_SIZE * CHUNK_SIZE;
+        file.seek(SeekFrom::Start(start))?;
+        let chunk = &mut buf[..(position - start) as usize];
+        file.read_exact(chunk)?;
+        for line_start in line_starts(chunk, start, None, encoding).rev() {
+            // A newline at the very end ends the last line instead of
+            // starting a new one
+            if line_start != length {
+                newlines += 1;
+                if newlines == count {
+                    return Ok(line_start);
+                }
+            }
+        }
+        position = start;
+    }
+    Ok(0)
+}
+
+/// Returns the position of the last `count` entries in the journal export
+/// format, which end with an empty line.
+fn last_entries_position(file: &mut File, count: u64) -> io::Result<u64> {
+    let length = file.seek(SeekFrom::End(0))?;
+    if count == 0 {
+        return Ok(length);
+    }
+    let mut buf = vec![0; CHUNK_SIZE as usize];
+    let mut position = length;
+    let mut entries = 0;
+    // The byte after the one being looked at
+    let mut next = None;
+    while position > 0 {
+        let size = CHUNK_SIZE.min(position);
+        position -= size;
+        file.seek(SeekFrom::Start(position))?;
+        let chunk = &mut buf[..size as usize];
+        file.read_exact(chunk)?;
+        for (i, &byte) in chunk.iter().enumerate().rev() {
+            let empty_line = byte == b'\n' && next == Some(b'\n');
+            next = Some(byte);
+            let entry_start = position + i as u64 + 2;
+            if empty_line && entry_start != length {
+                entries += 1;
+                if entries == count {
+                    return Ok(entry_start);
+                }
+            }
+        }
+    }
+    Ok(0)
+}
+
+/// Reads a compressed file, which can't be seeked, up to where reading
+/// should start and returns that position in the decompressed data. While
+/// looking for the last lines or bytes only those are kept, what was read
+/// after the start is put back into the decompressor.
+/// In the journal export format lines are entries.
+fn start_decompressed(
+    decompressor: &mut Decompressor,
+    start: Start,
+    encoding: Encoding,
+    journal: bool,
+) -> io::Result<u64> {
+    let mut target = match start {
+        Start::Beginning => return Ok(0),
+        Start::FromByte(byte) => Some(encoding.align(byte.saturating_sub(1))),
+        Start::FromLine(line) if line <= 1 => return Ok(0),
+        _ => None,
+    };
+    let mut buf = vec![0; CHUNK_SIZE as usize];
+    // Data that was read from position `base` on
+    let mut kept = Vec::new();
+    let mut base = 0;
+    // Size of `kept` when it was last cut down to the last lines or bytes
+    let mut cut_size = 0;
+    let mut newlines = 0;
+    // The last byte that was read, which may start a UTF-16 newline
+    let mut previous = None;
+    loop {
+        let size = decompressor.read(&mut buf)?;
+        let data = &buf[..size];
+        let end = base + kept.len() as u64;
+        if let (Start::FromLine(line), None) = (start, target) {
+            let line_starts: Box<dyn Iterator<Item = u64>> = if journal {
+                Box::new(entry_starts(data, previous).map(|start| end + start as u64))
+            } else {
+                Box::new(line_starts(data, end, previous, encoding))
+            };
+            for line_start in line_starts {
+                newlines += 1;
+                if newlines + 1 == line {
+                    target = Some(line_start);
+                    break;
+                }
+            }
+        }
+        previous = data.last().copied().or(previous);
+        kept.extend_from_slice(data);
+        let end = base + kept.len() as u64;
+        if let Some(target) = target {
+            if target <= end || size == 0 {
+                let target = target.min(end);
+                kept.drain(..(target - base) as usize);
+                decompressor.unread(kept);
+                return Ok(target);
+            }
+        }
+        // Cutting down is only worth it once enough was added
+        let cut = match start {
+            _ if size != 0 && kept.len() < 2 * cut_size + CHUNK_SIZE as usize => 0,
+            Start::LastLines(count) if journal => last_entries_offset(&kept, count),
+            Start::LastLines(count) => last_lines_offset(&kept, base, count, encoding),
+            Start::LastBytes(count) => {
+                let start = base + kept.len().saturating_sub(count as usize) as u64;
+                (encoding.align(start) - base) as usize
+            }
+            // Nothing before the line or byte to start at is kept
+            _ => kept.len(),
+        };
+        kept.drain(..cut);
+        base += cut as u64;
+        cut_size = kept.len();
+        if size == 0 {
+            decompressor.unread(kept);
+            return Ok(base);
+        }
+    }
+}
+
+/// Returns where the last `count` lines of `data` start, `base` is the
+/// position of `data` in the file.
+fn last_lines_offset(data: &[u8], base: u64, count: u64, encoding: Encoding) -> usize {
+    if count == 0 {
+        return data.len();
+    }
+    let end = base + data.len() as u64;
+    let mut newlines = 0;
+    for line_start in line_starts(data, base, None, encoding).rev() {
+        // A newline at the very end ends the last line instead of starting
+        // a new one
+        if line_start < end {
+            newlines += 1;
+            if newlines == count {
+                return (line_start - base) as usize;
+            }
+        }
+    }
+    0
+}
+
+/// Returns where the last `count` entries of `data` start, `data` starts at
+/// the start of an entry or of the file.
+fn last_entries_offset(data: &[u8], count: u64) -> usize {
+    if count == 0 {
+        return data.len();
+    }
+    let mut entries = 0;
+    for entry_start in entry_starts(data, None).rev() {
+        // An empty line at the very end ends the last entry
+        if entry_start < data.len() {
+            entries += 1;
+            if entries == count {
+                return entry_start;
+            }
+        }
+    }
+    0
+}
+
+/// Returns where the entries after the empty lines in `data` start,
+/// `previous` is the byte in front of it.
+fn entry_starts(data: &[u8], previous: Option<u8>) -> impl DoubleEndedIterator<Item = usize> + '_ {
+    data.iter().enumerate().filter_map(move |(i, &byte)| {
+        let before = match i {
+            0 => previous,
+            _ => Some(data[i - 1]),
+        };
+        (byte == b'\n' && before == Some(b'\n')).then_some(i + 1)
+    })
+}
+
+/// Returns the position of the given entry in the journal export format, or
+/// the end of the file if it has fewer entries.
+fn entry_position(file: &mut File, entry: u64) -> io::Result<u64> {
+    let mut buf = vec![0; CHUNK_SIZE as usize];
+    let mut position = 0;
+    let mut entries = 0;
+    let mut previous = None;
+    file.seek(SeekFrom::Start(0))?;
+    while entries + 1 < entry {
+        let size = file.read(&mut buf)?;
+        if size == 0 {
+            break;
+        }
+        for entry_start in entry_starts(&buf[..size], previous) {
+            entries += 1;
+            if entries + 1 == entry {
+                return Ok(position + entry_start as u64);
+            }
+        }
+        previous = Some(buf[size - 1]);
+        position += size as u64;
+    }
+    Ok(position)
+}
+
+/// Returns the position of the given line, or the end of the file if it has
+/// fewer lines.
+fn line_position(file: &mut File, line: u64, encoding: Encoding) -> io::Result<u64> {
+    let mut buf = vec![0; CHUNK_SIZE as usize];
+    let mut position = 0;
+    let mut newlines = 0;
+    let mut previous = None;
+    file.seek(SeekFrom::Start(0))?;
+    while newlines + 1 < line {
+        let size = file.read(&mut buf)?;
+        if size == 0 {
+            break;
+        }
+        for line_start in line_starts(&buf[..size], position, previous, encoding) {
+            newlines += 1;
+            if newlines + 1 == line {
+                return Ok(line_start);
+            }
+        }
+        previous = Some(buf[size - 1]);
+        position += size as u64;
+    }
+    Ok(position)
+}
+
+/// Returns the position after the first newline at or after `position`, or
+/// `position` itself if there is no newline in the next chunk. That is
+/// rounded down to a UTF-16 code unit.
+fn next_line_position(file: &mut File, position: u64, encoding: Encoding) -> u64 {
+    let mut buf = vec![0; CHUNK_SIZE as usize];
+    file.seek(SeekFrom::Start(position)).unwrap();
+    let size = file.read(&mut buf).unwrap_or(0);
+    let next = line_starts(&buf[..size], position, None, encoding).next();
+    next.unwrap_or(encoding.align(position))
+}
+
+/// Returns where the lines after the newlines in `data` start, `base` is the
+/// position of `data` in the file and `previous` the byte in front of it.
+fn line_starts(
+    data: &[u8],
+    base: u64,
+    previous: Option<u8>,
+    encoding: Encoding,
+) -> impl DoubleEndedIterator<Item = u64> + '_ {
+    data.iter().enumerate().filter_map(move |(i, &byte)| {
+        let before = match i {
+            0 => previous,
+            _ => Some(data[i - 1]),
+        };
+        encoding.line_start(base + i as u64, before, byte)
+    })
+}
+
+/// Parses a number of bytes with an optional K, M, or G suffix.
+pub fn parse_size(arg: &str) -> Result<u64, String> {
+    let (number, unit) = match arg.char_indices().find(|(_, c)| c.is_ascii_alphabetic()) {
+        Some((i, _)) => arg.split_at(i),
+        None => (arg, ""),
+    };
+    let unit = match unit.to_ascii_uppercase().trim_end_matches(['B', 'I']) {
+        "" => 1,
+        "K" => 1 << 10,
+        "M" => 1 << 20,
+        "G" => 1 << 30,
+        _ => return Err(format!("{arg}: unknown unit")),
+    };
+    let number: u64 = number
+        .trim()
+        .parse()
+        .map_err(|error| format!("{arg}: {error}"))?;
+    number
+        .checked_mul(unit)
+        .ok_or_else(|| format!("{arg}: number too large"))
+}
+
+#[cfg(target_family = "unix")]
+fn is_fifo(file_name: &str) -> bool {
+    use std::os::unix::fs::FileTypeExt;
+    std::fs::metadata(file_name).is_ok_and(|metadata| metadata.file_type().is_fifo())
+}
+
+#[cfg(target_family = "windows")]
+fn is_fifo(_file_name: &str) -> bool {
+    false
+}
+
+/// Returns the absolute path of `file`, which is also how it appears in
+/// events from watching its parent directory.
+fn watched_path(file: &str) -> io::Result<PathBuf> {
+    let path = Path::new(file);
+    let parent = match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    };
+    let file_name = path
+        .file_name()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "not a file name"))?;
+    Ok(parent.canonicalize()?.join(file_name))
+}
+
+/// Expands glob patterns in `files`, arguments that are not patterns are
+/// kept as they are so they can be waited for. So are existing files whose
+/// names contain pattern characters.
+pub fn expand_globs(files: &[String]) -> Result<Vec<String>, String> {
+    let mut result = Vec::new();
+    for file in files {
+        if !file.contains(['*', '?', '[']) || Path::new(file).exists() {
+            result.push(file.clone());
+            continue;
+        }
+        let paths = glob::glob(file).map_err(|error| format!("{file}: {error}"))?;
+        let count = result.len();
+        for path in paths.flatten() {
+            if path.is_file() {
+                result.push(path.to_string_lossy().into_owned());
+            }
+        }
+        if result.len() == count {
+            return Err(format!("{file}: no matching files"));
+        }
+    }
+    Ok(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    /// A file in the temporary directory that is removed when dropped.
+    struct TempFile(PathBuf);
+
+    impl TempFile {
+        fn new(name: &str, data: &[u8]) -> Self {
+            let path = std::env::temp_dir().join(format!("viewlog-{}-{name}", std::process::id()));
+            std::fs::write(&path, data).unwrap();
+            Self(path)
+        }
+
+        fn name(&self) -> &str {
+            self.0.to_str().unwrap()
+        }
+
+        fn append(&self, data: &[u8]) {
+            let mut file = File::options().append(true).open(&self.0).unwrap();
+            file.write_all(data).unwrap();
+        }
+    }
+
+    impl Drop for TempFile {
+        fn drop(&mut self) {
+            std::fs::remove_file(&self.0).ok();
+        }
+    }
+
+    fn options(start: Start, encoding: Encoding) -> SourceOptions {
+        SourceOptions {
+            wait: false,
+            start,
+            skip_ahead: None,
+            encoding,
+            carriage_return: false,
+            journal: false,
+        }
+    }
+
+    /// Reads what is in the file now, returns the complete lines and the
+    /// incomplete one.
+    fn read(source: &mut Source) -> (Vec<String>, String) {
+        let mut updates = Vec::new();
+        source.read_all(&mut updates);
+        let lines = updates
+            .into_iter()
+            .filter_map(|update| match update {
+                Update::Line(line) => Some(line),
+                _ => None,
+            })
+            .collect();
+        let partial = source.partial().map_or("", |(text, _)| text);
+        (lines, partial.to_string())
+    }
+
+    fn gzip(data: &[u8]) -> Vec<u8> {
+        let mut encoder = flate2::write::GzEncoder::new(Vec::new(), Default::default());
+        encoder.write_all(data).unwrap();
+        encoder.finish().unwrap()
+    }
+
+    fn utf16le(text: &str) -> Vec<u8> {
+        text.encode_utf16().flat_map(u16::to_le_bytes).collect()
+    }
+
+    fn utf16be(text: &str) -> Vec<u8> {
+        text.encode_utf16().flat_map(u16::to_be_bytes).collect()
+    }
+
+    #[test]
+    fn sizes() {
+        assert_eq!(parse_size("512"), Ok(512));
+        assert_eq!(parse_size("4K"), Ok(4096));
+        assert_eq!(parse_size("2 MiB"), Ok(2 << 20));
+        assert_eq!(parse_size("1g"), Ok(1 << 30));
+        assert!(parse_size("1T").is_err());
+        assert!(parse_size("99999999999G").is_err());
+    }
+
+    #[test]
+    fn existing_file_with_pattern_characters() {
+        let file = TempFile::new("app[1].log", b"");
+        let files = vec![file.name().to_string()];
+        assert_eq!(expand_globs(&files), Ok(files));
+        // A pattern that matches nothing is still an error
+        let pattern = file.name().replace("[1]", "[2]");
+        assert!(expand_globs(&[pattern]).is_err());
+    }
+
+    #[test]
+    fn pipe_that_cannot_be_opened() {
+        let path = std::env::temp_dir().join(format!("viewlog-{}-missing", std::process::id()));
+        let options = options(Start::Beginning, Encoding::Utf8);
+        let mut source = Source::stream("pipe", Input::Pipe(path), options);
+        let (sender, receiver) = std::sync::mpsc::channel();
+        source.spawn_reader(move || sender.send(()).unwrap());
+        receiver.recv().unwrap();
+        let mut updates = Vec::new();
+        source.read_all(&mut updates);
+        assert!(matches!(&updates[..], [Update::Error(_), Update::Ended]));
+    }
+
+    /// Reads the journal export in the file from `start` on, returns the
+    /// lines of the entries. The second entry of the fixture has a message
+    /// with two lines.
+    fn entries(file: &TempFile, start: Start) -> Vec<String> {
+        let options = SourceOptions {
+            journal: true,
+            ..options(start, Encoding::Utf8)
+        };
+        let mut source = Source::open(file.name(), options).unwrap();
+        let mut updates = Vec::new();
+        source.read_all(&mut updates);
+        updates
+            .into_iter()
+            .filter_map(|update| match update {
+                Update::Entry(text, _) => Some(text),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// Returns what `updates` are, with the text of lines.
+    fn describe(updates: &[Update]) -> Vec<&str> {
+        updates
+            .iter()
+            .map(|update| match update {
+                Update::Line(text) => text.as_str(),
+                Update::Rotated => "rotated",
+                Update::Removed => "removed",
+                _ => "other",
+            })
+            .collect()
+    }
+
+    #[test]
+    fn rest_read_before_rotation() {
+        let file = TempFile::new("rotated.log", b"one\n");
+        let mut source = Source::open(file.name(), options(Start::Beginning, Encoding::Utf8));
+        let source = source.as_mut().unwrap();
+        assert_eq!(read(source), (vec!["one".into()], "".into()));
+        file.append(b"two\nthr");
+        let old = TempFile(PathBuf::from(format!("{}.1", file.name())));
+        std::fs::rename(file.name(), old.name()).unwrap();
+        std::fs::write(file.name(), b"four\n").unwrap();
+        let mut updates = Vec::new();
+        source.on_rename(&mut updates);
+        // The rest of the old file is left for `read_chunk`
+        assert!(updates.is_empty());
+        source.read_all(&mut updates);
+        assert_eq!(describe(&updates), ["two", "thr", "rotated", "four"]);
+
+        file.append(b"five\n");
+        std::fs::remove_file(file.name()).unwrap();
+        let mut updates = Vec::new();
+        source.on_remove(&mut updates);
+        assert!(updates.is_empty());
+        source.read_all(&mut updates);
+        assert_eq!(describe(&updates), ["five", "removed"]);
+        assert!(!source.is_open());
+    }
+
+    #[test]
+    fn utf16_byte_positions() {
+        for (encoding, data) in [
+            (Encoding::Utf16Le, utf16le("hello\nworld\n")),
+            (Encoding::Utf16Be, utf16be("hello\nworld\n")),
+        ] {
+            let file = TempFile::new("utf16-bytes", &data);
+            // Byte 19 is in the middle of the `l` of `world`
+            let mut source = Source::open(file.name(), options(Start::LastBytes(5), encoding));
+            assert_eq!(
+                read(source.as_mut().unwrap()),
+                (vec!["ld".into()], "".into())
+            );
+            let mut source = Source::open(file.name(), options(Start::FromByte(6), encoding));
+            let lines = vec!["llo".to_string(), "world".to_string()];
+            assert_eq!(read(source.as_mut().unwrap()), (lines, "".into()));
+            let mut source = Source::open(file.name(), options(Start::FromLine(2), encoding));
+            assert_eq!(
+                read(source.as_mut().unwrap()),
+                (vec!["world".into()], "".into())
+            );
+        }
+    }
+
+    #[test]
+    fn utf16_skip_ahead() {
+        let skip = |text: &str, skip_ahead| {
+            let file = TempFile::new("utf16-skip", &utf16le(text));
+            let options = SourceOptions {
+                skip_ahead: Some(skip_ahead),
+                ..options(Start::Beginning, Encoding::Utf16Le)
+            };
+            let mut source = Source::open(file.name(), options).unwrap();
+            read(&mut source)
+        };
+        // Skipping to the line after the next newline
+        assert_eq!(skip("hello\nworld\nab", 9), (vec![], "ab".into()));
+        // Without a newline reading starts at the code unit
+        assert_eq!(skip("hello\nworld", 5), (vec![], "rld".into()));
+    }
+
+    #[test]
+    fn gzip_members_appended() {
+        let first = gzip(b"one\ntwo\n");
+        let file = TempFile::new("members.gz", &first[..10]);
+        let mut source = Source::open(file.name(), options(Start::Beginning, Encoding::Utf8));
+        let source = source.as_mut().unwrap();
+        assert_eq!(read(source), (vec![], "".into()));
+        file.append(&first[10..]);
+        let lines = vec!["one".to_string(), "two".to_string()];
+        assert_eq!(read(source), (lines, "".into()));
+        file.append(&gzip(b"three\nfo"));
+        assert_eq!(read(source), (vec!["three".into()], "fo".into()));
+        file.append(&gzip(b"ur\n"));
+        assert_eq!(read(source), (vec!["four".into()], "".into()));
+    }
+
+    /// Writes the file in pieces and reads after each one, the lines must
+    /// come out once and in order.
+    fn read_in_pieces(name: &str, data: &[u8]) {
+        let expected: Vec<String> = (1..=20000).map(|n| format!("line {n}")).collect();
+        let file = TempFile::new(name, &[]);
+        let mut source = Source::open(file.name(), options(Start::Beginning, Encoding::Utf8));
+        let source = source.as_mut().unwrap();
+        let mut lines = Vec::new();
+        let mut partial = String::new();
+        for (index, piece) in data.chunks(1000).enumerate() {
+            file.append(piece);
+            let (new, rest) = read(source);
+            lines.extend(new);
+            partial = rest;
+            assert_eq!(lines, expected[..lines.len()], "after piece {index}");
+            // Lines come out before the frame or stream is complete
+            if index == data.len() / 1000 / 2 {
+                assert!(!lines.is_empty());
+            }
+        }
+        assert_eq!((lines, partial), (expected, "".into()));
+    }
+
+    #[test]
+    fn zstd_frame_completed() {
+        // One frame with a window of 1 KiB, so its start is output before
+        // it is complete
+        read_in_pieces("lines.zst", include_bytes!("../tests/data/lines.zst"));
+    }
+
+    #[test]
+    fn xz_streams_completed() {
+        // Two streams of 10000 lines each
+        read_in_pieces("lines.xz", include_bytes!("../tests/data/lines.xz"));
+    }
+
+    #[test]
+    fn corrupt_data() {
+        let file = TempFile::new("corrupt.gz", &gzip(b"one\n"));
+        let mut source = Source::open(file.name(), options(Start::Beginning, Encoding::Utf8));
+        let source = source.as_mut().unwrap();
+        assert_eq!(read(source), (vec!["one".into()], "".into()));
+        file.append(b"\x1f\x8b\x08\x00not deflate data");
+        let mut updates = Vec::new();
+        source.read_all(&mut updates);
+        assert!(matches!(&updates[..], [Update::Error(_)]));
+        // Nothing is read after the error
+        file.append(&gzip(b"two\n"));
+        assert_eq!(read(source), (vec![], "".into()));
+
+        // Corrupt data that is followed by more is not taken for a frame
+        // that is still being written
+        let mut data = include_bytes!("../tests/data/lines.zst").to_vec();
+        data[1000..1100].fill(0xff);
+        let file = TempFile::new("corrupt.zst", &data);
+        let mut source = Source::open(file.name(), options(Start::Beginning, Encoding::Utf8));
+        let mut updates = Vec::new();
+        source.as_mut().unwrap().read_all(&mut updates);
+        assert!(matches!(updates.last(), Some(Update::Error(_))));
+    }
+
+    #[test]
+    fn last_entries_compressed() {
+        let export = include_bytes!("../tests/data/journal.export");
+        let plain = TempFile::new("last.export", export);
+        let compressed = TempFile::new("last.export.gz", &gzip(export));
+        for count in 0..5 {
+            let lines = entries(&plain, Start::LastLines(count));
+            assert_eq!(lines.len(), [0, 1, 3, 4, 4][count as usize]);
+            let decompressed = entries(&compressed, Start::LastLines(count));
+            assert_eq!(decompressed, lines, "last {count} entries");
+        }
+    }
+
+    #[test]
+    fn from_entry() {
+        let export = include_bytes!("../tests/data/journal.export");
+        let plain = TempFile::new("from.export", export);
+        let compressed = TempFile::new("from.export.gz", &gzip(export));
+        for entry in 0..6 {
+            let lines = entries(&plain, Start::FromLine(entry));
+            assert_eq!(lines.len(), [4, 4, 3, 1, 0, 0][entry as usize]);
+            let decompressed = entries(&compressed, Start::FromLine(entry));
+            assert_eq!(decompressed, lines, "from entry {entry}");
+        }
+    }
+
+    /// Returns where reading starts for `start`, after checking that it
+    /// starts at the same place when the file is compressed.
+    fn start_position(name: &str, data: &[u8], start: Start) -> u64 {
+        let file = TempFile::new(name, data);
+        let mut handle = File::open(file.name()).unwrap();
+        let position = match start {
+            Start::LastLines(count) => last_lines_position(&mut handle, count, Encoding::Utf8),
+            Start::FromLine(line) => line_position(&mut handle, line, Encoding::Utf8),
+            _ => unreachable!(),
+        }
+        .unwrap();
+        let file = TempFile::new(&format!("{name}.gz"), &gzip(data));
+        let handle = File::open(file.name()).unwrap();
+        let mut decompressor = Decompressor::detect(Path::new(file.name()), &handle)
+            .unwrap()
+            .unwrap();
+        let decompressed = start_decompressed(&mut decompressor, start, Encoding::Utf8, false);
+        assert_eq!(decompressed.unwrap(), position);
+        let mut rest = Vec::new();
+        decompressor.read_to_end(&mut rest).unwrap();
+        assert!(rest == data[position as usize..]);
+        position
+    }
+
+    #[test]
+    fn line_positions() {
+        for (name, data) in [
+            ("newline", &b"a\nbb\nccc\n"[..]),
+            ("no-newline", b"a\nbb\nccc"),
+        ] {
+            let length = data.len() as u64;
+            let last = |count| start_position(name, data, Start::LastLines(count));
+            assert_eq!(last(0), length);
+            assert_eq!(last(1), 5);
+            assert_eq!(last(2), 2);
+            assert_eq!(last(3), 0);
+            assert_eq!(last(10), 0);
+            let line = |line| start_position(name, data, Start::FromLine(line));
+            assert_eq!(line(1), 0);
+            assert_eq!(line(2), 2);
+            assert_eq!(line(3), 5);
+            // Past the last line reading starts at the end
+            assert_eq!(line(4), length);
+            assert_eq!(line(10), length);
+        }
+        assert_eq!(
+            last_lines_offset(b"a\nbb\nccc\n", 100, 1, Encoding::Utf8),
+            5
+        );
+        assert_eq!(last_lines_offset(b"a\nbb\nccc", 100, 2, Encoding::Utf8), 2);
+        assert_eq!(last_lines_offset(b"a\nbb\nccc", 100, 0, Encoding::Utf8), 8);
+        assert_eq!(last_lines_offset(b"a\nbb\nccc", 100, 5, Encoding::Utf8), 0);
+    }
+
+    #[test]
+    fn from_byte_past_end() {
+        let file = TempFile::new("bytes.log", b"one\ntwo\n");
+        let mut source = Source::open(file.name(), options(Start::FromByte(1000), Encoding::Utf8));
+        let source = source.as_mut().unwrap();
+        let mut updates = Vec::new();
+        source.read_all(&mut updates);
+        assert!(updates.is_empty());
+        file.append(b"three\n");
+        assert_eq!(read(source), (vec!["three".into()], "".into()));
+    }
+
+    #[test]
+    fn line_positions_at_chunk_boundary() {
+        let size = CHUNK_SIZE as usize;
+        // Lines start at the first byte of the second and third chunk
+        let mut data = [vec![b'x'; size - 1], vec![b'y'; size - 1]].join(&b'\n');
+        data.push(b'\n');
+        let last = |data: &[u8], count| start_position("chunks", data, Start::LastLines(count));
+        let line = |data: &[u8], line| start_position("chunks", data, Start::FromLine(line));
+        assert_eq!(last(&data, 1), CHUNK_SIZE);
+        assert_eq!(last(&data, 2), 0);
+        assert_eq!(line(&data, 2), CHUNK_SIZE);
+        assert_eq!(line(&data, 3), 2 * CHUNK_SIZE);
+        data.extend_from_slice(b"zz");
+        assert_eq!(last(&data, 1), 2 * CHUNK_SIZE);
+        assert_eq!(last(&data, 2), CHUNK_SIZE);
+        assert_eq!(line(&data, 3), 2 * CHUNK_SIZE);
+        // A line starts at the second byte of the second chunk
+        let data = [vec![b'x'; size], b"yy\n".to_vec()].join(&b'\n');
+        assert_eq!(last(&data, 1), CHUNK_SIZE + 1);
+        assert_eq!(last(&data, 2), 0);
+        assert_eq!(line(&data, 2), CHUNK_SIZE + 1);
+    }
+}
@@ -0,0 +1,259 @@
+use chrono::{
+    format::{Item, StrftimeItems},
+    DateTime, Datelike, Duration, FixedOffset, Local, NaiveDate, NaiveDateTime, NaiveTime,
+    TimeZone,
+};
+use regex::{Captures, Regex};
+
+/// Finds the time a line was logged at in its text.
+pub struct TimeParser {
+    /// Format given by the user, used instead of the built-in ones
+    format: Option<String>,
+    rfc3339: Regex,
+    common_log: Regex,
+    syslog: Regex,
+    epoch: Regex,
+}
+
+impl TimeParser {
+    pub fn new(format: Option<String>) -> Self {
+        Self {
+            format,
+            rfc3339: Regex::new(
+                r"\b(\d{4}-\d{2}-\d{2})[T ](\d{2}:\d{2}:\d{2}(?:[.,]\d+)?) ?(Z|[+-]\d{2}:?\d{2})?",
+            )
+            .unwrap(),
+            common_log: Regex::new(r"\[(\d{2}/[A-Z][a-z]{2}/\d{4}:\d{2}:\d{2}:\d{2} [+-]\d{4})\]")
+                .unwrap(),
+            syslog: Regex::new(r"^(?:<\d+>)?([A-Z][a-z]{2} +\d{1,2} \d{2}:\d{2}:\d{2})\b").unwrap(),
+            epoch: Regex::new(r"^\[?(\d{13}|\d{10}(?:\.\d{1,9})?)\b").unwrap(),
+        }
+    }
+
+    /// Returns the time found in `text`, which has no escape sequences.
+    pub fn parse(&self, text: &str) -> Option<DateTime<Local>> {
+        if let Some(format) = &self.format {
+            return parse_custom(text, format);
+        }
+        if let Some(captures) = self.rfc3339.captures(text) {
+            return parse_rfc3339(&captures);
+        }
+        if let Some(captures) = self.common_log.captures(text) {
+            let time = DateTime::parse_from_str(&captures[1], "%d/%b/%Y:%H:%M:%S %z").ok()?;
+            return Some(time.with_timezone(&Local));
+        }
+        if let Some(captures) = self.syslog.captures(text) {
+            return parse_syslog(&captures[1]);
+        }
+        if let Some(captures) = self.epoch.captures(text) {
+            return parse_epoch(&captures[1]);
+        }
+        None
+    }
+}
+
+/// Checks a format given by the user, in the syntax of `strftime`.
+pub fn parse_format(arg: &str) -> Result<String, String> {
+    if StrftimeItems::new(arg).any(|item| matches!(item, Item::Error)) {
+        return Err(format!("{arg}: invalid time format"));
+    }
+    Ok(arg.to_string())
+}
+
+/// Dates and times such as `2024-05-01T12:34:56.789+02:00`, times without an
+/// offset are local.
+fn parse_rfc3339(captures: &Captures) -> Option<DateTime<Local>> {
+    let date = NaiveDate::parse_from_str(&captures[1], "%Y-%m-%d").ok()?;
+    let time = NaiveTime::parse_from_str(&captures[2].replace(',', "."), "%H:%M:%S%.f").ok()?;
+    let naive = date.and_time(time);
+    let offset = match captures.get(3).map(|offset| offset.as_str()) {
+        None => return Local.from_local_datetime(&naive).earliest(),
+        Some("Z") => FixedOffset::east_opt(0)?,
+        Some(offset) => {
+            let digits = offset[1..].replace(':', "");
+            let hours: i32 = digits[..2].parse().ok()?;
+            let minutes: i32 = digits[2..].parse().ok()?;
+            let seconds = hours * 3600 + minutes * 60;
+            if offset.starts_with('-') {
+                FixedOffset::west_opt(seconds)?
+            } else {
+                FixedOffset::east_opt(seconds)?
+            }
+        }
+    };
+    let time = offset.from_local_datetime(&naive).single()?;
+    Some(time.with_timezone(&Local))
+}
+
+/// Times such as `May  1 12:34:56`, which have no year. The current year is
+/// assumed unless that is in the future, then it is from last year.
+fn parse_syslog(text: &str) -> Option<DateTime<Local>> {
+    let now = Local::now();
+    let parse = |year: i32| {
+        let naive = NaiveDateTime::parse_from_str(&format!("{year} {text}"), "%Y %b %d %H:%M:%S");
+        Local.from_local_datetime(&naive.ok()?).earliest()
+    };
+    match parse(now.year()) {
+        Some(time) if time > now + Duration::days(1) => parse(now.year() - 1),
+        time => time,
+    }
+}
+
+/// Seconds since the epoch with an optional fraction, or milliseconds.
+fn parse_epoch(text: &str) -> Option<DateTime<Local>> {
+    if text.len() == 13 {
+        return Local.timestamp_millis_opt(text.parse().ok()?).single();
+    }
+    let (seconds, fraction) = text.split_once('.').unwrap_or((text, "0"));
+    // Pad the fraction to nanoseconds
+    let nanos = format!("{fraction:0<9}").parse().ok()?;
+    Local.timestamp_opt(seconds.parse().ok()?, nanos).single()
+}
+
+/// Looks for a time in the format given by the user at the start of the line
+/// and after each space or opening bracket. Formats without a date are
+/// taken as today, those without an offset as local time.
+fn parse_custom(text: &str, format: &str) -> Option<DateTime<Local>> {
+    let starts = text
+        .char_indices()
+        .filter(|&(i, _)| i == 0 || text[..i].ends_with([' ', '\t', '[', '(']))
+        .map(|(i, _)| &text[i..]);
+    for start in starts {
+        if let Ok((time, _)) = DateTime::parse_and_remainder(start, format) {
+            return Some(time.with_timezone(&Local));
+        }
+        let naive = NaiveDateTime::parse_and_remainder(start, format)
+            .map(|(naive, _)| naive)
+            .or_else(|_| {
+                NaiveTime::parse_and_remainder(start, format)
+                    .map(|(time, _)| Local::now().date_naive().and_time(time))
+            });
+        if let Ok(naive) = naive {
+            return Local.from_local_datetime(&naive).earliest();
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(text: &str) -> Option<DateTime<Local>> {
+        TimeParser::new(None).parse(text)
+    }
+
+    fn utc(text: &str) -> DateTime<FixedOffset> {
+        DateTime::parse_from_rfc3339(text).unwrap()
+    }
+
+    fn local(date: (i32, u32, u32), time: (u32, u32, u32)) -> DateTime<Local> {
+        let (year, month, day) = date;
+        let (hour, minute, second) = time;
+        Local
+            .with_ymd_and_hms(year, month, day, hour, minute, second)
+            .earliest()
+            .unwrap()
+    }
+
+    #[test]
+    fn rfc3339() {
+        let time = parse("2024-06-01T12:34:56.789+02:00 GET /").unwrap();
+        assert_eq!(time, utc("2024-06-01T10:34:56.789Z"));
+        assert_eq!(
+            parse("level=info ts=2024-06-01T10:34:56Z msg=x").unwrap(),
+            utc("2024-06-01T10:34:56Z")
+        );
+        assert_eq!(
+            parse("2024-06-01 12:34:56 -0130").unwrap(),
+            utc("2024-06-01T14:04:56Z")
+        );
+        // Without an offset the time is local, a comma may separate the
+        // fraction
+        let time = parse("2024-06-01 12:34:56,250 INFO started").unwrap();
+        assert_eq!(
+            time,
+            local((2024, 6, 1), (12, 34, 56)) + Duration::milliseconds(250)
+        );
+    }
+
+    #[test]
+    fn common_log() {
+        let line = r#"127.0.0.1 - - [01/Jun/2024:12:34:56 +0200] "GET / HTTP/1.1" 200 512"#;
+        assert_eq!(parse(line).unwrap(), utc("2024-06-01T10:34:56Z"));
+    }
+
+    #[test]
+    fn syslog() {
+        let now = Local::now();
+        for line in [
+            "Jan  2 03:04:05 host sshd[1]: x",
+            "<13>Dec 31 23:59:59 host x",
+        ] {
+            let time = parse(line).unwrap();
+            // The year is not given, so it is the most recent one that is
+            // not in the future
+            assert!(time <= now + Duration::days(1));
+            assert!(time > now - Duration::days(366));
+        }
+        let time = parse("Jan  2 03:04:05 host sshd[1]: x").unwrap();
+        assert_eq!((time.month(), time.day()), (1, 2));
+        assert_eq!(time.time(), NaiveTime::from_hms_opt(3, 4, 5).unwrap());
+    }
+
+    #[test]
+    fn epoch() {
+        let time = utc("2024-06-01T10:14:56Z");
+        assert_eq!(parse("1717236896 started").unwrap(), time);
+        assert_eq!(
+            parse("[1717236896.5] x").unwrap(),
+            time + Duration::milliseconds(500)
+        );
+        assert_eq!(
+            parse("1717236896123 x").unwrap(),
+            time + Duration::milliseconds(123)
+        );
+        // Numbers elsewhere in the line are not taken for times
+        assert_eq!(parse("pid 1717236896"), None);
+        assert_eq!(parse("17172368961 x"), None);
+    }
+
+    #[test]
+    fn no_time() {
+        assert_eq!(parse("just a message"), None);
+        assert_eq!(parse("2024-13-01 12:34:56 x"), None);
+    }
+
+    #[test]
+    fn custom_format() {
+        let parser = TimeParser::new(Some("%d.%m.%Y %H:%M:%S".to_string()));
+        assert_eq!(
+            parser.parse("[01.06.2024 12:34:56] x").unwrap(),
+            local((2024, 6, 1), (12, 34, 56))
+        );
+        assert_eq!(
+            parser.parse("at 01.06.2024 12:34:56").unwrap(),
+            local((2024, 6, 1), (12, 34, 56))
+        );
+        // The built-in formats are not used
+        assert_eq!(parser.parse("2024-06-01T12:34:56Z"), None);
+
+        let parser = TimeParser::new(Some("%Y/%m/%d %H:%M:%S %z".to_string()));
+        assert_eq!(
+            parser.parse("2024/06/01 12:34:56 +0200 x").unwrap(),
+            utc("2024-06-01T10:34:56Z")
+        );
+
+        // Times without a date are from today
+        let parser = TimeParser::new(Some("%H:%M:%S".to_string()));
+        let time = parser.parse("(12:34:56) x").unwrap();
+        assert_eq!(time.date_naive(), Local::now().date_naive());
+        assert_eq!(time.time(), NaiveTime::from_hms_opt(12, 34, 56).unwrap());
+    }
+
+    #[test]
+    fn format_check() {
+        assert_eq!(parse_format("%H:%M:%S").unwrap(), "%H:%M:%S");
+        assert!(parse_format("%H:%").is_err());
+    }
+}
@@ -0,0 +1,1716 @@
+use crate::{
+    clear_screen,
+    command::Command,
+    escape::{self, sanitize, strip, Escapes, Kind, Sgr},
+    filter::Filter,
+    goto,
+    highlight::{builtin_rules, Rule},
+    input::Key,
+    repeat_ascii,
+    source::{Source, Update},
+    timestamp::TimeParser,
+    width::{cell_widths, expand_tabs},
+    Commandline, Result, Split, Wrap,
+};
+use chrono::{DateTime, Local};
+use regex::Regex;
+use std::{
+    collections::VecDeque,
+    io::{self, stdout, Write},
+    path::Path,
+    time::{Duration, Instant},
+};
+use unicode_width::{UnicodeWidthChar, UnicodeWidthStr};
+
+/// Chunks read from a source before other events are handled
+const CHUNKS_PER_EVENT: usize = 16;
+
+pub struct CursorInfo {
+    /// Position of the top-left corner on the terminal
+    top: usize,
+    left: usize,
+    term_lines: usize,
+    term_cols: usize,
+    cursor_line: usize,
+    cursor_col: usize,
+    save_line: usize,
+    save_col: usize,
+}
+
+impl CursorInfo {
+    pub fn new() -> Self {
+        let (term_cols, term_lines) = term_size::dimensions().unwrap_or_default();
+        Self::pane(0, 0, term_lines, term_cols)
+    }
+
+    /// Cursor for a pane of the given size at the given position.
+    pub fn pane(top: usize, left: usize, lines: usize, cols: usize) -> Self {
+        Self {
+            top,
+            left,
+            // Reserve one line for the status bar
+            term_lines: lines.saturating_sub(1),
+            term_cols: cols,
+            cursor_line: 0,
+            cursor_col: 0,
+            save_line: 0,
+            save_col: 0,
+        }
+    }
+
+    fn newline(&mut self) {
+        if self.cursor_line != self.term_lines {
+            self.cursor_line += 1;
+        }
+        self.cursor_col = 0;
+    }
+
+    fn add(&mut self, n: usize) {
+        self.cursor_col += n;
+    }
+
+    fn save(&mut self) {
+        self.save_line = self.cursor_line;
+        self.save_col = self.cursor_col;
+    }
+
+    fn restore(&mut self) {
+        self.cursor_line = self.save_line;
+        self.cursor_col = self.save_col;
+        self.goto(self.cursor_line, self.cursor_col);
+    }
+
+    /// Moves the cursor to a position relative to the pane.
+    fn goto(&self, line: usize, col: usize) {
+        goto(self.top + line, self.left + col);
+    }
+
+    fn fits(&self, col: usize, cells: usize) -> bool {
+        col + cells < self.term_cols
+    }
+
+    fn clear(&mut self) {
+        self.cursor_line = 0;
+        self.cursor_col = 0;
+    }
+}
+
+pub struct Viewer {
+    pub sources: Vec<Source>,
+    /// Colored source tags, only used when viewing multiple files
+    tags: Vec<String>,
+    tag_width: usize,
+    timestamps: bool,
+    /// Set when the time shown is the one found in the line
+    line_time: Option<TimeParser>,
+    discard_old: bool,
+    /// The most recent lines, for scrolling back
+    lines: VecDeque<Line>,
+    max_lines: usize,
+    /// Number of lines that were removed from the front of `lines`, so lines
+    /// can be referred to by an index that does not change
+    dropped: usize,
+    /// Set while paused
+    scroll: Option<Scroll>,
+    /// Set while the user enters text in the status bar
+    prompt: Option<Prompt>,
+    /// The last search, its matches are highlighted
+    search: Option<Search>,
+    filter: Filter,
+    escapes: Escapes,
+    wrap: Wrap,
+    tab_width: usize,
+    /// How long incomplete lines are waited for before they are shown
+    partial_timeout: Option<Duration>,
+    /// Number of rows taken up by the incomplete line currently shown below
+    /// the others, zero if it is hidden by the filter
+    partial: Option<usize>,
+    /// Number of columns scrolled to the right, when not wrapping
+    hscroll: usize,
+    /// Highlight rules, earlier rules take precedence
+    rules: Vec<Rule>,
+    /// Number of lines in the buffer hidden by the filter
+    hidden: usize,
+    /// Style set by each source at the end of its last complete line, which
+    /// its next line starts with
+    file_styles: Vec<Sgr>,
+    /// Number of bytes skipped because too much was added at once
+    skipped: u64,
+    focused: bool,
+    cursor: CursorInfo,
+    /// Content of the rows of a split pane, split panes cannot use the
+    /// scrolling of the terminal so we need to redraw them ourselves.
+    rows: Option<Vec<Row>>,
+    time: DateTime<Local>,
+    what_time: &'static str,
+    /// The command whose output is viewed, instead of files
+    command: Option<Command>,
+}
+
+/// Where a line is split into rows, as indices of its characters.
+#[derive(Default)]
+struct Breaks {
+    /// Characters that start a new row
+    rows: Vec<usize>,
+    /// Characters before this one are scrolled out of view
+    first: usize,
+    /// Character replaced by `…` because the rest does not fit
+    cut: Option<usize>,
+}
+
+#[derive(Default, Clone)]
+struct Row {
+    text: String,
+    width: usize,
+}
+
+struct Line {
+    source: usize,
+    time: DateTime<Local>,
+    text: String,
+    /// Style set by the file before the line
+    style: Sgr,
+    /// Whether the line passes the filter
+    visible: bool,
+}
+
+impl Line {
+    /// Returns the style set by the file after the line.
+    fn end_style(&self) -> Sgr {
+        let chars: Vec<char> = self.text.chars().collect();
+        let mut style = self.style.clone();
+        let mut i = 0;
+        while i < chars.len() {
+            if chars[i] == '\x1b' {
+                let sequence = escape::parse(&chars, i);
+                if sequence.sgr {
+                    style.apply(&chars[i..i + sequence.length]);
+                }
+                i += sequence.length;
+            } else {
+                i += 1;
+            }
+        }
+        style
+    }
+}
+
+/// A line index and a row in that line.
+type Position = (usize, usize);
+
+struct Scroll {
+    /// Position shown at the top of the screen
+    top: Position,
+    /// Number of lines that arrived while paused
+    new_lines: usize,
+}
+
+struct Prompt {
+    /// The key that opened the prompt
+    kind: char,
+    text: String,
+    /// Where the view was when the prompt was opened, `None` if following
+    origin: Option<Position>,
+}
+
+struct Search {
+    regex: Regex,
+    backward: bool,
+    /// Line of the current match
+    line: Option<usize>,
+}
+
+impl Viewer {
+    /// Creates a viewer for `files`, or for the output of the command if
+    /// one is given. If `pane` is given the viewer is drawn in that part of
+    /// the terminal, otherwise it uses the whole terminal.
+    pub fn new(args: &Commandline, files: &[String], pane: Option<CursorInfo>) -> Result<Self> {
+        let options = args.source_options();
+        let mut command = None;
+        let mut sources = files
+            .iter()
+            .map(|file| Source::open(file, options))
+            .collect::<io::Result<Vec<_>>>()?;
+        if !args.command.is_empty() {
+            let (child, stdout, stderr) = Command::spawn(&args.command)?;
+            let mut stderr = Source::from_reader("stderr", Box::new(stderr), options);
+            stderr.tint = args.stderr_style.clone();
+            sources = vec![
+                Source::from_reader("stdout", Box::new(stdout), options),
+                stderr,
+            ];
+            command = Some(child);
+        }
+        let line_time = args.line_time || args.time_format.is_some();
+        let (tags, tag_width) = if files.len() > 1 {
+            source_tags(files)
+        } else {
+            (Vec::new(), 0)
+        };
+        let timestamps = args.timestamps || line_time;
+        let file_styles = vec![Sgr::default(); sources.len()];
+        let split = pane.is_some();
+        let cursor = pane.unwrap_or_else(CursorInfo::new);
+        // Leave at least as many columns for the text as the timestamp and
+        // the tag take up
+        let gutter = if timestamps { "00:00:00 ".len() } else { 0 }
+            + if tag_width != 0 { tag_width + 1 } else { 0 };
+        if cursor.term_lines == 0 || cursor.term_cols < 2 * (gutter + 1) {
+            return Err(format!(
+                "terminal is too small, panes need at least 2 lines and {} columns",
+                2 * (gutter + 1)
+            )
+            .into());
+        }
+        Ok(Self {
+            sources,
+            tags,
+            tag_width,
+            timestamps,
+            line_time: line_time.then(|| TimeParser::new(args.time_format.clone())),
+            discard_old: args.discard_old,
+            lines: VecDeque::new(),
+            max_lines: args.buffer.max(1),
+            dropped: 0,
+            scroll: None,
+            prompt: None,
+            search: None,
+            filter: Filter::new(&args.grep, &args.exclude)?,
+            hidden: 0,
+            file_styles,
+            skipped: 0,
+            escapes: args.escapes,
+            wrap: args.wrap,
+            tab_width: args.tab_width,
+            partial_timeout: args.partial_timeout.map(Duration::from_millis),
+            partial: None,
+            hscroll: 0,
+            rules: if args.no_highlight {
+                args.highlight.clone()
+            } else {
+                [args.highlight.clone(), builtin_rules()].concat()
+            },
+            focused: true,
+            rows: split.then(|| vec![Row::default(); cursor.term_lines]),
+            cursor,
+            time: Local::now(),
+            what_time: "Started",
+            command,
+        })
+    }
+
+    /// Reads what was added to a source, one chunk at a time so large
+    /// additions are shown while they are being read. At most
+    /// `CHUNKS_PER_EVENT` chunks are read so keys are still handled while a
+    /// file grows fast, returns whether there may be more to read.
+    pub fn on_change(&mut self, source: usize) -> bool {
+        for _ in 0..CHUNKS_PER_EVENT {
+            let mut updates = Vec::new();
+            if !self.sources[source].read_chunk(&mut updates) {
+                return false;
+            }
+            self.apply(source, updates);
+        }
+        true
+    }
+
+    pub fn on_rename(&mut self, source: usize) -> bool {
+        let mut updates = Vec::new();
+        self.sources[source].on_rename(&mut updates);
+        self.apply(source, updates);
+        self.on_change(source)
+    }
+
+    pub fn on_remove(&mut self, source: usize) -> bool {
+        let mut updates = Vec::new();
+        self.sources[source].on_remove(&mut updates);
+        self.apply(source, updates);
+        self.on_change(source)
+    }
+
+    fn apply(&mut self, source: usize, updates: Vec<Update>) {
+        // An incomplete line that is shown may have changed
+        if updates.is_empty() && self.partial.is_none() {
+            return;
+        }
+        let single = self.sources.len() == 1;
+        let what = if single {
+            "File".to_string()
+        } else {
+            self.sources[source].name.clone()
+        };
+        self.hide_partial();
+        let mut notice = None;
+        for update in updates {
+            match update {
+                Update::Line(text) => {
+                    let line = self.make_line(source, text, None);
+                    // The tint is not part of the style of the file
+                    if self.sources[source].tint.is_none() {
+                        self.file_styles[source] = line.end_style();
+                    }
+                    self.add_line(line);
+                }
+                Update::Entry(text, style) => {
+                    // Entries do not continue each other, so the style of
+                    // the source stays the default
+                    let line = self.make_line(source, text, style);
+                    self.add_line(line);
+                }
+                Update::Truncated => {
+                    self.file_styles[source] = Sgr::default();
+                    notice = Some(format!("{what} truncated"));
+                    if single {
+                        self.truncate();
+                    }
+                }
+                Update::Rotated => {
+                    self.file_styles[source] = Sgr::default();
+                    notice = Some(format!("{what} rotated"));
+                    if single {
+                        self.time = Local::now();
+                        self.what_time = "Rotated";
+                    }
+                }
+                Update::Created => {
+                    self.file_styles[source] = Sgr::default();
+                    notice = Some(format!("{what} created"));
+                    if single {
+                        self.time = Local::now();
+                        self.what_time = "Created";
+                    }
+                }
+                Update::Removed => {
+                    notice = Some(format!("{what} removed"));
+                }
+                Update::Ended => {
+                    notice = Some(if single {
+                        "End of input".to_string()
+                    } else {
+                        format!("End of {what}")
+                    });
+                    if let Some(command) = &mut self.command {
+                        command.poll();
+                    }
+                }
+                Update::Skipped(bytes) => self.skipped += bytes,
+                Update::Error(error) => notice = Some(format!("{what}: {error}")),
+            }
+        }
+        self.show_partial();
+        self.print_header(notice.as_deref());
+        stdout().flush().ok();
+    }
+
+    /// Makes a line from text that was read, `style` are SGR parameters
+    /// that replace the tint of the source.
+    fn make_line(&self, source: usize, text: String, style: Option<&str>) -> Line {
+        // Tabs are kept so patterns and times can refer to them, they are
+        // expanded when the line is laid out
+        let mut text = sanitize(text, self.escapes);
+        // The style of the previous line is continued, unless the line has
+        // a style of its own
+        let mut file_style = self.file_styles[source].clone();
+        if let Some(tint) = style.or(self.sources[source].tint.as_deref()) {
+            text.insert_str(0, &format!("\x1b[{tint}m"));
+            file_style = Sgr::default();
+        }
+        // Lines without a time of their own get the time they were read
+        let time = self.line_time.as_ref().and_then(|parser| {
+            let chars: Vec<char> = text.chars().collect();
+            parser.parse(&strip(&chars).0)
+        });
+        Line {
+            source,
+            time: time.unwrap_or_else(Local::now),
+            visible: self.filter.matches(&text),
+            text,
+            style: file_style,
+        }
+    }
+
+    /// Returns when `on_timeout` should be called next.
+    pub fn deadline(&self) -> Option<Instant> {
+        let running = self.command.as_ref().and_then(Command::next_second);
+        self.partial_deadline().into_iter().chain(running).min()
+    }
+
+    /// Shows incomplete lines that waited long enough and updates the state
+    /// of the command.
+    pub fn on_timeout(&mut self) {
+        self.show_partial();
+        if let Some(command) = &mut self.command {
+            command.poll();
+            self.print_header(None);
+        }
+        stdout().flush().ok();
+    }
+
+    /// Returns when an incomplete line should be shown, if there is one
+    /// waiting for that.
+    fn partial_deadline(&self) -> Option<Instant> {
+        let timeout = self.partial_timeout?;
+        if self.partial.is_some() || self.scroll.is_some() {
+            return None;
+        }
+        self.sources
+            .iter()
+            .filter_map(|source| source.partial())
+            .map(|(_, since)| since + timeout)
+            .min()
+    }
+
+    /// Shows an incomplete line below the others if it waited long enough,
+    /// it stays there until the rest of the line arrives.
+    pub fn show_partial(&mut self) {
+        match self.partial_deadline() {
+            Some(deadline) if deadline <= Instant::now() => {}
+            _ => return,
+        }
+        // The line that waited the longest
+        let (source, text) = self
+            .sources
+            .iter()
+            .enumerate()
+            .filter_map(|(i, source)| source.partial().map(|(text, since)| (since, i, text)))
+            .min_by_key(|&(since, ..)| since)
+            .map(|(_, i, text)| (i, text.to_string()))
+            .unwrap();
+        let line = self.make_line(source, text, None);
+        self.partial = Some(if line.visible {
+            self.print_line(&line)
+        } else {
+            0
+        });
+    }
+
+    /// Removes the incomplete line that is shown, so it can be replaced.
+    fn hide_partial(&mut self) {
+        let rows = match self.partial.take() {
+            Some(0) | None => return,
+            Some(rows) => rows,
+        };
+        // Rows that scrolled out of view stay in the scrollback buffer
+        let start = self.cursor.cursor_line.saturating_sub(rows);
+        if let Some(rows) = &mut self.rows {
+            rows[start..=self.cursor.cursor_line].fill(Row::default());
+            for line in start..=self.cursor.cursor_line {
+                self.draw_row(line, &Row::default());
+            }
+        } else {
+            self.cursor.goto(start, 0);
+            // Also clears the status bar, it is drawn again afterwards
+            print!("\x1b[J");
+        }
+        self.cursor.cursor_line = start;
+        self.cursor.cursor_col = 0;
+        self.cursor.goto(start, 0);
+    }
+
+    fn truncate(&mut self) {
+        self.time = Local::now();
+        self.what_time = "Created";
+        if self.discard_old {
+            self.dropped += self.lines.len();
+            self.lines.clear();
+            self.hidden = 0;
+        }
+        if self.scroll.is_some() {
+            // Keep showing what the user scrolled to
+            return;
+        }
+        self.partial = None;
+        self.cursor.clear();
+        if let Some(rows) = &mut self.rows {
+            rows.fill(Row::default());
+            self.redraw();
+        } else {
+            clear_screen(self.discard_old);
+        }
+    }
+
+    /// Prints text, `width` is the number of cells it takes up.
+    fn put(&mut self, text: &str, width: usize) {
+        print!("{}", text);
+        self.cursor.add(width);
+        if let Some(rows) = &mut self.rows {
+            let row = &mut rows[self.cursor.cursor_line];
+            row.text.push_str(text);
+            row.width += width;
+        }
+    }
+
+    /// Redraws all rows of a split pane.
+    fn redraw(&mut self) {
+        if let Some(rows) = &self.rows {
+            for (line, row) in rows.iter().enumerate() {
+                self.draw_row(line, row);
+            }
+            self.cursor
+                .goto(self.cursor.cursor_line, self.cursor.cursor_col);
+        }
+    }
+
+    /// Draws a row at the given line, replacing what was there before.
+    fn draw_row(&self, line: usize, row: &Row) {
+        self.cursor.goto(line, 0);
+        print!(
+            "{}\x1b[0m{}",
+            row.text,
+            repeat_ascii(' ', self.cursor.term_cols.saturating_sub(row.width))
+        );
+    }
+
+    fn newline(&mut self) {
+        let rows = match &mut self.rows {
+            Some(rows) => rows,
+            None => {
+                println!("\x1b[K");
+                self.cursor.newline();
+                return;
+            }
+        };
+        // Clear the rest of the row without touching other panes
+        print!(
+            "{}",
+            repeat_ascii(
+                ' ',
+                self.cursor.term_cols.saturating_sub(self.cursor.cursor_col)
+            )
+        );
+        if self.cursor.cursor_line + 1 < self.cursor.term_lines {
+            self.cursor.newline();
+            self.cursor.goto(self.cursor.cursor_line, 0);
+        } else {
+            rows.remove(0);
+            rows.push(Row::default());
+            self.cursor.cursor_col = 0;
+            self.redraw();
+        }
+    }
+
+    fn add_line(&mut self, line: Line) {
+        if !line.visible {
+            self.hidden += 1;
+        } else if let Some(scroll) = &mut self.scroll {
+            scroll.new_lines += 1;
+        } else {
+            self.print_line(&line);
+        }
+        self.lines.push_back(line);
+        if self.lines.len() > self.max_lines {
+            if self.lines.pop_front().is_some_and(|line| !line.visible) {
+                self.hidden -= 1;
+            }
+            self.dropped += 1;
+        }
+    }
+
+    /// Splits a line into the rows it takes up on the screen.
+    fn layout(&self, line: &Line) -> Vec<Row> {
+        if !line.visible {
+            return Vec::new();
+        }
+        let mut rows = vec![Row::default()];
+        let mut timestamp_size;
+        if self.timestamps {
+            let timestamp = line.time.format("%H:%M:%S ").to_string();
+            timestamp_size = timestamp.width();
+            rows[0].text = format!("\x1b[2m{}\x1b[0m", timestamp);
+        } else {
+            timestamp_size = 0;
+        }
+        if let Some(tag) = self.tags.get(line.source) {
+            rows[0].text.push_str(tag);
+            rows[0].text.push(' ');
+            timestamp_size += self.tag_width + 1;
+        }
+        rows[0].width = timestamp_size;
+        let timestamp_space = repeat_ascii(' ', timestamp_size);
+        let chars: Vec<char> = line.text.chars().collect();
+        let (highlights, styles) = self.matches(&chars);
+        let (chars, origins) = expand_tabs(&chars, self.tab_width);
+        let highlights: Vec<bool> = origins.iter().map(|&i| highlights[i]).collect();
+        let styles: Vec<Option<usize>> = origins.iter().map(|&i| styles[i]).collect();
+        // Whether highlighting is currently turned on, it is turned off at
+        // the end of each row and after escape sequences from the file.
+        let mut highlighting = false;
+        // The rule whose style is currently applied
+        let mut applied: Option<usize> = None;
+        // Style set by the file, used to restore it after a match of a rule
+        // and at the start of continuation rows. Each row ends with a reset
+        // so the style does not bleed into the gutter or the status bar.
+        let mut file_style = line.style.clone();
+        rows[0].text.push_str(&file_style.sequence());
+        let widths = cell_widths(&chars);
+        let breaks = self.breaks(&chars, &widths, timestamp_size);
+        let mut next_row = breaks.rows.iter().peekable();
+        let mut i = 0;
+        while i < chars.len() {
+            if next_row.next_if_eq(&&i).is_some() {
+                let row = rows.last_mut().unwrap();
+                if applied.take().is_some() || highlighting || !file_style.is_default() {
+                    row.text.push_str("\x1b[0m");
+                }
+                highlighting = false;
+                rows.push(Row {
+                    text: timestamp_space.clone() + &file_style.sequence(),
+                    width: timestamp_size,
+                });
+            }
+            let c = chars[i];
+            let row = rows.last_mut().unwrap();
+            if c == '\x1b' {
+                // Collect and print at once since most terminals don't let you
+                // print escape sequences character by character, and so they
+                // always stay in one row.
+                let sequence = escape::parse(&chars, i);
+                let length = sequence.length;
+                let seq: String = chars[i..i + length].iter().collect();
+                // Incomplete sequences would swallow what is printed after
+                // them, so they are dropped.
+                if sequence.kind != Kind::Incomplete {
+                    row.text.push_str(&seq);
+                }
+                if sequence.sgr {
+                    file_style.apply(&chars[i..i + length]);
+                    if let Some(rule) = applied {
+                        // Matches keep the style of their rule
+                        row.text
+                            .push_str(&format!("\x1b[{}m", self.rules[rule].style));
+                    }
+                }
+                i += length;
+                highlighting = false;
+                continue;
+            }
+            if i < breaks.first {
+                // Scrolled out of view
+                i += 1;
+                continue;
+            }
+            let (c, w) = if breaks.cut == Some(i) {
+                ('…', 1)
+            } else {
+                (c, widths[i])
+            };
+            if styles[i] != applied {
+                if applied.is_some() {
+                    row.text.push_str("\x1b[0m");
+                    row.text.push_str(&file_style.sequence());
+                    highlighting = false;
+                }
+                if let Some(rule) = styles[i] {
+                    row.text
+                        .push_str(&format!("\x1b[{}m", self.rules[rule].style));
+                }
+                applied = styles[i];
+            }
+            if highlights[i] != highlighting {
+                highlighting = highlights[i];
+                if highlighting {
+                    row.text.push_str("\x1b[7m");
+                } else {
+                    // The file or the rule may have turned on reverse video
+                    // as well
+                    row.text.push_str("\x1b[0m");
+                    row.text.push_str(&file_style.sequence());
+                    if let Some(rule) = applied {
+                        row.text
+                            .push_str(&format!("\x1b[{}m", self.rules[rule].style));
+                    }
+                }
+            }
+            row.text.push(c);
+            row.width += w;
+            if breaks.cut == Some(i) {
+                break;
+            }
+            i += 1;
+        }
+        let row = rows.last_mut().unwrap();
+        if applied.is_some() || highlighting || !file_style.is_default() {
+            row.text.push_str("\x1b[0m");
+        }
+        rows
+    }
+
+    /// Decides where a line is wrapped or cut off, `gutter` is the width
+    /// taken by the timestamp and the tag.
+    fn breaks(&self, chars: &[char], widths: &[usize], gutter: usize) -> Breaks {
+        let mut breaks = Breaks::default();
+        let mut width = gutter;
+        // Position after the last whitespace in the current row and the
+        // width of the row up to there
+        let mut space = None;
+        // The previous visible character
+        let mut previous = None;
+        let mut column = 0;
+        let mut i = 0;
+        while i < chars.len() {
+            if chars[i] == '\x1b' {
+                i += escape::parse(chars, i).length;
+                continue;
+            }
+            let w = widths[i];
+            match self.wrap {
+                // The rest of a grapheme cluster is hidden with its start
+                Wrap::None if column < self.hscroll || (w == 0 && breaks.first == i) => {
+                    column += w;
+                    breaks.first = i + 1;
+                    i += 1;
+                    continue;
+                }
+                Wrap::None if !self.cursor.fits(width, w) => {
+                    // Replace the previous character by the marker if this
+                    // one does not leave enough space
+                    breaks.cut = match previous {
+                        Some(previous) if !self.cursor.fits(width, 1) => Some(previous),
+                        _ => Some(i),
+                    };
+                    break;
+                }
+                Wrap::None => {}
+                _ if self.cursor.fits(width, w) => {}
+                wrap => {
+                    if let (Wrap::Word, Some((start, before))) = (wrap, space) {
+                        breaks.rows.push(start);
+                        width = gutter + width - before;
+                    }
+                    if !self.cursor.fits(width, w) {
+                        // The whitespace may be right before this character,
+                        // which then starts the row already
+                        if breaks.rows.last() != Some(&i) {
+                            breaks.rows.push(i);
+                        }
+                        width = gutter;
+                    }
+                    space = None;
+                }
+            }
+            previous = Some(i);
+            width += w;
+            if chars[i].is_whitespace() {
+                space = Some((i + 1, width));
+            }
+            i += 1;
+        }
+        breaks
+    }
+
+    /// Returns which characters of a line are part of a search match, and
+    /// the highlight rule matching each character.
+    fn matches(&self, chars: &[char]) -> (Vec<bool>, Vec<Option<usize>>) {
+        let mut highlights = vec![false; chars.len()];
+        let mut styles = vec![None; chars.len()];
+        if self.search.is_none() && self.rules.is_empty() {
+            return (highlights, styles);
+        }
+        let (text, indices) = strip(chars);
+        let offsets: Vec<usize> = text.char_indices().map(|(offset, _)| offset).collect();
+        // Calls `mark` with the index in `chars` of each character that is
+        // part of a match of `regex`.
+        let for_each_match = |regex: &Regex, mark: &mut dyn FnMut(usize)| {
+            for m in regex.find_iter(&text) {
+                let first = offsets.partition_point(|&offset| offset < m.start());
+                let last = offsets.partition_point(|&offset| offset < m.end());
+                for &i in &indices[first..last] {
+                    mark(i);
+                }
+            }
+        };
+        if let Some(search) = &self.search {
+            for_each_match(&search.regex, &mut |i| highlights[i] = true);
+        }
+        // Go backwards so earlier rules take precedence
+        for (rule, Rule { regex, .. }) in self.rules.iter().enumerate().rev() {
+            for_each_match(regex, &mut |i| styles[i] = Some(rule));
+        }
+        (highlights, styles)
+    }
+
+    /// Prints a line below the others, returns the number of rows it took.
+    fn print_line(&mut self, line: &Line) -> usize {
+        if self.rows.is_some() {
+            // Another pane may have moved the cursor
+            self.cursor
+                .goto(self.cursor.cursor_line, self.cursor.cursor_col);
+        }
+        let rows = self.layout(line);
+        for (i, row) in rows.iter().enumerate() {
+            if i != 0 {
+                self.newline();
+            }
+            self.put(&row.text, row.width);
+        }
+        self.newline();
+        self.print_header(None);
+        stdout().flush().ok();
+        rows.len()
+    }
+
+    pub fn on_key(&mut self, key: Key) {
+        if self.prompt.is_some() {
+            self.on_prompt_key(key);
+            return;
+        }
+        let page = self.cursor.term_lines.saturating_sub(1).max(1);
+        match key {
+            Key::Up | Key::Char('k') => self.scroll_up(1),
+            Key::Down | Key::Char('j') => self.scroll_down(1),
+            Key::PageUp | Key::Char('b') => self.scroll_up(page),
+            Key::PageDown | Key::Char(' ') => self.scroll_down(page),
+            Key::Home | Key::Char('g') => {
+                self.pause();
+                self.scroll.as_mut().unwrap().top = (self.dropped, 0);
+            }
+            Key::Char('p') => self.pause(),
+            Key::Left | Key::Right | Key::Char('h') | Key::Char('l') if self.wrap == Wrap::None => {
+                let step = (self.cursor.term_cols / 2).max(1);
+                if matches!(key, Key::Left | Key::Char('h')) {
+                    self.hscroll = self.hscroll.saturating_sub(step);
+                } else {
+                    self.hscroll = (self.hscroll + step).min(self.max_hscroll());
+                }
+                self.refresh();
+                return;
+            }
+            Key::End | Key::Char('G') | Key::Char('F') => {
+                self.follow();
+                return;
+            }
+            Key::Char(kind @ ('/' | '?' | '&')) => {
+                self.prompt = Some(Prompt {
+                    kind,
+                    text: String::new(),
+                    origin: self.scroll.as_ref().map(|_| self.top()),
+                });
+                self.print_header(None);
+                stdout().flush().ok();
+                return;
+            }
+            Key::Char('n') => {
+                self.next_match(false);
+                return;
+            }
+            Key::Char('N') => {
+                self.next_match(true);
+                return;
+            }
+            Key::Char('r') if self.command.is_some() => {
+                self.restart();
+                return;
+            }
+            _ => return,
+        }
+        self.draw_window();
+    }
+
+    /// Stops the command and starts it again, its output continues below
+    /// what it printed before. The new streams are read once
+    /// `Source::spawn_reader` is called.
+    fn restart(&mut self) {
+        // Show what the old command printed up to now
+        for source in 0..self.sources.len() {
+            self.on_change(source);
+        }
+        self.hide_partial();
+        let notice = match self.command.as_mut().unwrap().restart() {
+            Ok((stdout, stderr)) => {
+                self.sources[0].replace_reader(Box::new(stdout));
+                self.sources[1].replace_reader(Box::new(stderr));
+                self.time = Local::now();
+                self.what_time = "Restarted";
+                None
+            }
+            Err(error) => Some(format!("Restart failed: {error}")),
+        };
+        self.print_header(notice.as_deref());
+        stdout().flush().ok();
+    }
+
+    fn on_prompt_key(&mut self, key: Key) {
+        let prompt = self.prompt.as_mut().unwrap();
+        match key {
+            Key::Char(c) => prompt.text.push(c),
+            Key::Backspace => {
+                if prompt.text.pop().is_none() {
+                    // Backspace on the empty prompt cancels it like in less
+                    self.cancel_prompt();
+                    return;
+                }
+            }
+            Key::Escape => {
+                self.cancel_prompt();
+                return;
+            }
+            Key::Enter => {
+                let prompt = self.prompt.take().unwrap();
+                let notice = if prompt.kind == '&' {
+                    self.submit_filter(&prompt.text)
+                } else {
+                    self.submit_search(&prompt)
+                };
+                self.refresh();
+                self.print_header(notice);
+                stdout().flush().ok();
+                return;
+            }
+            _ => return,
+        }
+        if prompt.kind == '&' {
+            self.print_header(None);
+            stdout().flush().ok();
+            return;
+        }
+        // Searches are incremental, show the result for what was entered
+        // so far.
+        let prompt = self.prompt.take().unwrap();
+        self.submit_search(&prompt);
+        self.prompt = Some(prompt);
+        self.refresh();
+    }
+
+    /// Closes the prompt and restores the view from before it was opened.
+    fn cancel_prompt(&mut self) {
+        let prompt = self.prompt.take().unwrap();
+        self.go_to(prompt.origin);
+        self.refresh();
+    }
+
+    /// Moves the view to the given position, or resumes following.
+    fn go_to(&mut self, position: Option<Position>) {
+        match position {
+            Some(position) => {
+                self.pause();
+                self.scroll.as_mut().unwrap().top = position;
+            }
+            None => self.scroll = None,
+        }
+    }
+
+    /// Searches for the pattern entered in the prompt, starting at where the
+    /// view was when the prompt was opened. Returns a notice if the pattern
+    /// is invalid or not found.
+    fn submit_search(&mut self, prompt: &Prompt) -> Option<&'static str> {
+        self.go_to(prompt.origin);
+        if prompt.text.is_empty() {
+            self.search = None;
+            return None;
+        }
+        let regex = match Regex::new(&prompt.text) {
+            Ok(regex) => regex,
+            Err(_) => return Some("Invalid pattern"),
+        };
+        let backward = prompt.kind == '?';
+        self.search = Some(Search {
+            regex,
+            backward,
+            line: None,
+        });
+        match self.find(self.search_start(backward), backward) {
+            Some(line) => {
+                self.jump_to_match(line);
+                None
+            }
+            None => Some("Pattern not found"),
+        }
+    }
+
+    /// Replaces the filter with the one entered in the prompt, `pattern`
+    /// shows only matching lines and `!pattern` hides matching lines. Returns
+    /// a notice if the pattern is invalid.
+    fn submit_filter(&mut self, text: &str) -> Option<&'static str> {
+        let filter = match text.strip_prefix('!') {
+            Some(pattern) => Filter::new(&[], &[pattern.to_string()]),
+            None if text.is_empty() => Ok(Filter::default()),
+            None => Filter::new(&[text.to_string()], &[]),
+        };
+        self.filter = match filter {
+            Ok(filter) => filter,
+            Err(_) => return Some("Invalid pattern"),
+        };
+        self.hidden = 0;
+        for line in self.lines.iter_mut() {
+            line.visible = self.filter.matches(&line.text);
+            if !line.visible {
+                self.hidden += 1;
+            }
+        }
+        None
+    }
+
+    /// Jumps to the next match of the last search, in the opposite direction
+    /// of the search if `reverse` is true.
+    fn next_match(&mut self, reverse: bool) {
+        let search = match &self.search {
+            Some(search) => search,
+            None => return,
+        };
+        let backward = search.backward != reverse;
+        let from = match (search.line, backward) {
+            (Some(line), false) => Some(line + 1),
+            (Some(line), true) => line.checked_sub(1),
+            (None, backward) => Some(self.search_start(backward)),
+        };
+        match from.and_then(|from| self.find(from, backward)) {
+            Some(line) => {
+                self.jump_to_match(line);
+                self.draw_window();
+            }
+            None => {
+                self.print_header(Some("Pattern not found"));
+                stdout().flush().ok();
+            }
+        }
+    }
+
+    /// Returns where a search without a current match starts, which is the
+    /// view while paused. While following everything in the buffer is above
+    /// the view, so searching forward starts at its first line.
+    fn search_start(&self, backward: bool) -> usize {
+        let visible = self.visible_lines();
+        match (backward, &self.scroll) {
+            (true, _) => visible.1,
+            (false, Some(_)) => visible.0,
+            (false, None) => self.dropped,
+        }
+    }
+
+    /// Returns the first line at or after `from` that matches the search, or
+    /// the last line at or before `from` if `backward` is true.
+    fn find(&self, from: usize, backward: bool) -> Option<usize> {
+        let regex = &self.search.as_ref()?.regex;
+        let is_match = |line: &usize| {
+            let line = &self.lines[line - self.dropped];
+            let chars: Vec<char> = line.text.chars().collect();
+            line.visible && regex.is_match(&strip(&chars).0)
+        };
+        let end = self.dropped + self.lines.len();
+        if backward {
+            (self.dropped..end.min(from + 1)).rev().find(is_match)
+        } else {
+            (from.max(self.dropped)..end).find(is_match)
+        }
+    }
+
+    /// Pauses and scrolls so the given line is at the top, or as close to it
+    /// as possible.
+    fn jump_to_match(&mut self, line: usize) {
+        self.pause();
+        self.scroll.as_mut().unwrap().top = (line, 0).min(self.last_page());
+        self.search.as_mut().unwrap().line = Some(line);
+    }
+
+    /// Returns the first and last line that are currently visible.
+    fn visible_lines(&self) -> (usize, usize) {
+        let top = match self.scroll {
+            Some(_) => self.top(),
+            None => self.last_page(),
+        };
+        let end = self.dropped + self.lines.len();
+        let mut rows = self.row_count_at(top.0).saturating_sub(top.1);
+        let mut line = top.0;
+        while rows < self.cursor.term_lines && line + 1 < end {
+            line += 1;
+            rows += self.row_count(line);
+        }
+        (top.0, line)
+    }
+
+    /// Returns how far the view can be scrolled to the right, which is
+    /// until the end of the widest line that is shown fits.
+    fn max_hscroll(&self) -> usize {
+        let (first, last) = self.visible_lines();
+        let widest = self
+            .lines
+            .range(first - self.dropped..(last + 1 - self.dropped).min(self.lines.len()))
+            .filter(|line| line.visible)
+            .map(|line| {
+                let chars: Vec<char> = line.text.chars().collect();
+                cell_widths(&expand_tabs(&chars, self.tab_width).0)
+                    .iter()
+                    .sum()
+            })
+            .max()
+            .unwrap_or(0);
+        let mut gutter = 0;
+        if self.timestamps {
+            gutter += "00:00:00 ".len();
+        }
+        if !self.tags.is_empty() {
+            gutter += self.tag_width + 1;
+        }
+        // The last column is kept free, like when a line is cut off
+        widest.saturating_sub(self.cursor.term_cols.saturating_sub(gutter + 1))
+    }
+
+    /// Like `row_count` but returns 0 for lines that are not in the buffer.
+    fn row_count_at(&self, line: usize) -> usize {
+        if line >= self.dropped && line < self.dropped + self.lines.len() {
+            self.row_count(line)
+        } else {
+            0
+        }
+    }
+
+    /// Redraws what is currently shown.
+    fn refresh(&mut self) {
+        if self.scroll.is_some() {
+            self.draw_window();
+        } else {
+            self.draw_tail();
+        }
+    }
+
+    /// Moves the viewer to a new area of the terminal and redraws it, the
+    /// buffer is wrapped to the new width.
+    pub fn resize(&mut self, mut cursor: CursorInfo) {
+        // Keep drawing when the terminal becomes too small, what does not
+        // fit is cut off by the terminal
+        cursor.term_lines = cursor.term_lines.max(1);
+        cursor.term_cols = cursor.term_cols.max(1);
+        if let Some(rows) = &mut self.rows {
+            *rows = vec![Row::default(); cursor.term_lines];
+        }
+        self.cursor = cursor;
+        self.draw_border();
+        self.refresh();
+    }
+
+    /// Draws the line separating a pane from the one to its left.
+    pub fn draw_border(&self) {
+        if self.rows.is_none() || self.cursor.left == 0 {
+            return;
+        }
+        for line in 0..=self.cursor.term_lines {
+            goto(self.cursor.top + line, self.cursor.left - 1);
+            print!("\u{2502}");
+        }
+    }
+
+    pub fn set_focused(&mut self, focused: bool) {
+        self.focused = focused;
+        self.print_header(None);
+        stdout().flush().ok();
+    }
+
+    /// Number of rows the line with the given index takes up.
+    fn row_count(&self, line: usize) -> usize {
+        self.layout(&self.lines[line - self.dropped]).len()
+    }
+
+    /// Returns the top position when showing the end of the buffer.
+    fn last_page(&self) -> Position {
+        let height = self.cursor.term_lines;
+        let mut rows = 0;
+        let mut line = self.dropped + self.lines.len();
+        while line > self.dropped {
+            line -= 1;
+            rows += self.row_count(line);
+            if rows >= height {
+                return (line, rows - height);
+            }
+        }
+        (self.dropped, 0)
+    }
+
+    /// Returns the top position while paused, lines that were removed from
+    /// the buffer in the mean time are skipped and the row is kept inside its
+    /// line if it got shorter after resizing.
+    fn top(&self) -> Position {
+        let (line, row) = self.scroll.as_ref().unwrap().top;
+        if line < self.dropped {
+            (self.dropped, 0)
+        } else {
+            (line, row.min(self.row_count_at(line).saturating_sub(1)))
+        }
+    }
+
+    fn pause(&mut self) {
+        if self.scroll.is_none() {
+            self.scroll = Some(Scroll {
+                top: self.last_page(),
+                new_lines: 0,
+            });
+        }
+    }
+
+    fn scroll_up(&mut self, n: usize) {
+        self.pause();
+        let (mut line, mut row) = self.top();
+        for _ in 0..n {
+            if row > 0 {
+                row -= 1;
+            } else if let Some(previous) = self.previous_visible(line) {
+                line = previous;
+                row = self.row_count(line) - 1;
+            } else {
+                break;
+            }
+        }
+        self.scroll.as_mut().unwrap().top = (line, row);
+    }
+
+    fn scroll_down(&mut self, n: usize) {
+        if self.scroll.is_none() {
+            return;
+        }
+        let last = self.last_page();
+        let (mut line, mut row) = self.top();
+        for _ in 0..n {
+            if (line, row) >= last {
+                break;
+            }
+            if row + 1 < self.row_count(line) {
+                row += 1;
+            } else {
+                // Skip lines hidden by the filter, there is a visible line
+                // below since we are not at the last page yet.
+                line += 1;
+                row = 0;
+                while self.row_count(line) == 0 {
+                    line += 1;
+                }
+            }
+        }
+        self.scroll.as_mut().unwrap().top = (line, row);
+    }
+
+    /// Returns the closest line before `line` that is not hidden by the
+    /// filter.
+    fn previous_visible(&self, line: usize) -> Option<usize> {
+        (self.dropped..line)
+            .rev()
+            .find(|&line| self.lines[line - self.dropped].visible)
+    }
+
+    /// Draws the part of the buffer that was scrolled to.
+    fn draw_window(&mut self) {
+        self.partial = None;
+        let height = self.cursor.term_lines;
+        let end = self.dropped + self.lines.len();
+        let (mut line, mut skip) = self.top();
+        let mut drawn = 0;
+        while drawn < height && line < end {
+            for row in self
+                .layout(&self.lines[line - self.dropped])
+                .iter()
+                .skip(skip)
+            {
+                if drawn == height {
+                    break;
+                }
+                self.draw_row(drawn, row);
+                drawn += 1;
+            }
+            skip = 0;
+            line += 1;
+        }
+        while drawn < height {
+            self.draw_row(drawn, &Row::default());
+            drawn += 1;
+        }
+        self.print_header(None);
+        stdout().flush().ok();
+    }
+
+    /// Stops scrolling and shows the end of the buffer like it would look if
+    /// the output was never paused.
+    fn follow(&mut self) {
+        if self.scroll.take().is_none() {
+            return;
+        }
+        self.draw_tail();
+    }
+
+    /// Draws the end of the buffer.
+    fn draw_tail(&mut self) {
+        // Split panes always keep the row of the cursor empty
+        let count = if self.rows.is_some() {
+            self.cursor.term_lines.saturating_sub(1)
+        } else {
+            self.cursor.term_lines
+        };
+        let mut tail = Vec::new();
+        for line in self.lines.iter().rev() {
+            if tail.len() >= count {
+                break;
+            }
+            tail.extend(self.layout(line).into_iter().rev());
+        }
+        tail.truncate(count);
+        self.partial = None;
+        self.cursor.clear();
+        if let Some(rows) = &mut self.rows {
+            rows.fill(Row::default());
+            self.redraw();
+        } else {
+            self.cursor.goto(0, 0);
+        }
+        for row in tail.iter().rev() {
+            self.put(&row.text, row.width);
+            self.newline();
+        }
+        if self.rows.is_none() {
+            // Clear what is left of the old content
+            print!("\x1b[J");
+        }
+        self.show_partial();
+        self.print_header(None);
+        stdout().flush().ok();
+    }
+
+    pub fn print_header(&mut self, notice: Option<&str>) {
+        if let Some(prompt) = &self.prompt {
+            self.cursor.save();
+            // Show the end of the text if it does not fit
+            let width = self.cursor.term_cols.saturating_sub(3);
+            let text = tail_to_width(&prompt.text, width);
+            self.cursor.goto(self.cursor.term_lines, 0);
+            print!(
+                "\x1b[0m{}{}\x1b[7m \x1b[27m{}",
+                prompt.kind,
+                text,
+                repeat_ascii(' ', width - text.width())
+            );
+            self.cursor.restore();
+            return;
+        }
+        self.cursor.save();
+        let (label, name, mut rest) = if let Some(command) = &self.command {
+            command.describe()
+        } else if let [source] = &self.sources[..] {
+            if source.is_open() {
+                ("Viewing ", source.name.clone(), String::new())
+            } else {
+                ("Waiting for ", source.name.clone(), String::new())
+            }
+        } else {
+            let mut rest = " files".to_string();
+            let waiting = self.sources.iter().filter(|s| !s.is_open()).count();
+            if waiting != 0 {
+                rest.push_str(&format!(", {} waiting", waiting));
+            }
+            ("Viewing ", self.sources.len().to_string(), rest)
+        };
+        let name = visible_name(name);
+        // The state of a command is more important than its arguments
+        let reserved = if self.command.is_some() {
+            rest.width()
+        } else {
+            0
+        };
+        if self.filter.is_active() {
+            rest.push_str(&format!(
+                "   Filter {}, {} hidden",
+                self.filter.describe(),
+                self.hidden
+            ));
+        }
+        if self.skipped != 0 {
+            rest.push_str(&format!("   Skipped {}", format_size(self.skipped)));
+        }
+        if let Some(scroll) = &self.scroll {
+            rest.push_str("   Paused");
+            if scroll.new_lines != 0 {
+                rest.push_str(&format!(", {} new lines below", scroll.new_lines));
+            }
+        }
+        if let Some(notice) = notice {
+            // Notices may contain names of files and errors about them
+            rest.push_str("   ");
+            rest.push_str(&visible_name(notice.to_string()));
+        }
+        let time = format!("{} at {}", self.what_time, self.time.format("%H:%M:%S"));
+
+        // Cut off what does not fit, leaving one space on each side
+        let width = self.cursor.term_cols.saturating_sub(2);
+        let label = truncate_to_width(label, width);
+        let name = truncate_to_width(&name, (width - label.width()).saturating_sub(reserved));
+        let rest = truncate_to_width(&rest, width - label.width() - name.width());
+        let used = label.width() + name.width() + rest.width();
+
+        // Status bars of panes without focus are dimmed
+        let dim = if self.focused { "" } else { "\x1b[2m" };
+        // Reset first, in case a style from the file is still active
+        print!("\x1b[0;7m{}", dim);
+        self.cursor.goto(self.cursor.term_lines, 0);
+        print!(
+            " {}\x1b[1m{}\x1b[22m{}{}{}",
+            label,
+            name,
+            dim,
+            rest,
+            repeat_ascii(' ', self.cursor.term_cols - 1 - used)
+        );
+        if used + time.len() < width {
+            self.cursor.goto(
+                self.cursor.term_lines,
+                self.cursor.term_cols - time.len() - 1,
+            );
+            print!("{}", time);
+        }
+        print!("\x1b[22;27m");
+        self.cursor.restore();
+    }
+}
+
+/// Formats a number of bytes for displaying.
+fn format_size(bytes: u64) -> String {
+    const UNITS: [&str; 4] = ["B", "KiB", "MiB", "GiB"];
+    let mut size = bytes as f64;
+    let mut unit = 0;
+    while size >= 1024.0 && unit + 1 < UNITS.len() {
+        size /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{} {}", bytes, UNITS[0])
+    } else {
+        format!("{:.1} {}", size, UNITS[unit])
+    }
+}
+
+/// Returns the longest prefix of `s` that is at most `width` cells wide.
+fn truncate_to_width(s: &str, width: usize) -> &str {
+    let mut total = 0;
+    for (i, c) in s.char_indices() {
+        total += c.width().unwrap_or(0);
+        if total > width {
+            return &s[..i];
+        }
+    }
+    s
+}
+
+/// Returns the longest suffix of `s` that is at most `width` cells wide.
+fn tail_to_width(s: &str, width: usize) -> &str {
+    let mut total = 0;
+    for (i, c) in s.char_indices().rev() {
+        total += c.width().unwrap_or(0);
+        if total > width {
+            return &s[i + c.len_utf8()..];
+        }
+    }
+    s
+}
+
+/// Makes control characters in a file name, command line, or notice
+/// visible, so they are not interpreted by the terminal.
+fn visible_name(name: String) -> String {
+    sanitize(name, Escapes::None).replace('\t', " ")
+}
+
+/// Returns the source tags for `files` and their width. The tags are made
+/// from the file names and all have the same width.
+fn source_tags(files: &[String]) -> (Vec<String>, usize) {
+    const MAX_WIDTH: usize = 12;
+    const COLORS: [u8; 6] = [32, 33, 34, 35, 36, 31];
+    let names: Vec<String> = files
+        .iter()
+        .map(|file| {
+            let path = Path::new(file);
+            let name = path.file_stem().unwrap_or(path.as_os_str());
+            let name = visible_name(name.to_string_lossy().into_owned());
+            name.chars().take(MAX_WIDTH).collect()
+        })
+        .collect();
+    let width = names.iter().map(|name| name.width()).max().unwrap_or(0);
+    let tags = names
+        .iter()
+        .enumerate()
+        .map(|(i, name)| {
+            let padding = repeat_ascii(' ', width - name.width());
+            format!(
+                "\x1b[{}m{}{}\x1b[39m",
+                COLORS[i % COLORS.len()],
+                name,
+                padding
+            )
+        })
+        .collect();
+    (tags, width)
+}
+
+/// Divides the terminal into `count` panes, vertical panes are separated by
+/// a column for drawing a border.
+pub fn split_panes(split: Split, count: usize) -> Vec<CursorInfo> {
+    let (term_cols, term_lines) = term_size::dimensions().unwrap_or_default();
+    match split {
+        Split::Horizontal => {
+            let lines = term_lines / count;
+            (0..count)
+                .map(|i| {
+                    let top = i * lines;
+                    // The last pane gets the rest of the lines
+                    let lines = if i + 1 == count {
+                        term_lines - top
+                    } else {
+                        lines
+                    };
+                    CursorInfo::pane(top, 0, lines, term_cols)
+                })
+                .collect()
+        }
+        Split::Vertical => {
+            let cols = term_cols.saturating_sub(count - 1) / count;
+            (0..count)
+                .map(|i| {
+                    let left = i * (cols + 1);
+                    let cols = if i + 1 == count {
+                        term_cols.saturating_sub(left)
+                    } else {
+                        cols
+                    };
+                    CursorInfo::pane(0, left, term_lines, cols)
+                })
+                .collect()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::Parser;
+
+    /// A viewer of standard input in a pane with `cols` columns, which is
+    /// never read so lines are added by the test.
+    fn viewer(wrap: &str, cols: usize) -> Viewer {
+        let args = Commandline::parse_from(["viewlog", "--wrap", wrap, "-"]);
+        let files = ["-".to_string()];
+        Viewer::new(&args, &files, Some(CursorInfo::pane(0, 0, 3, cols))).unwrap()
+    }
+
+    fn breaks(viewer: &Viewer, text: &str) -> Breaks {
+        let chars: Vec<char> = text.chars().collect();
+        viewer.breaks(&chars, &cell_widths(&chars), 0)
+    }
+
+    #[test]
+    fn style_continued_on_next_line() {
+        let mut viewer = viewer("char", 20);
+        let mut rows = Vec::new();
+        for text in [
+            "\x1b[1;31mfirst",
+            "second",
+            "\x1b[22mthird\x1b[0m",
+            "fourth",
+        ] {
+            let line = viewer.make_line(0, text.to_string(), None);
+            viewer.file_styles[0] = line.end_style();
+            rows.extend(viewer.layout(&line).into_iter().map(|row| row.text));
+        }
+        assert_eq!(
+            rows,
+            [
+                "\x1b[1;31mfirst\x1b[0m",
+                "\x1b[1;31msecond\x1b[0m",
+                "\x1b[1;31m\x1b[22mthird\x1b[0m",
+                "fourth",
+            ]
+        );
+        // A line with a style of its own does not continue the previous one
+        let line = viewer.make_line(0, "\x1b[1mbold".to_string(), None);
+        viewer.file_styles[0] = line.end_style();
+        let line = viewer.make_line(0, "entry".to_string(), Some("33"));
+        assert_eq!(viewer.layout(&line)[0].text, "\x1b[33mentry\x1b[0m");
+    }
+
+    /// Returns the first row of each line in the buffer.
+    fn first_rows(viewer: &Viewer) -> Vec<String> {
+        let lines = viewer.lines.iter();
+        lines
+            .map(|line| viewer.layout(line).remove(0).text)
+            .collect()
+    }
+
+    #[test]
+    fn own_style_not_continued() {
+        let mut journal = viewer("char", 20);
+        let entries = vec![
+            Update::Entry("error entry".to_string(), Some("31")),
+            Update::Entry("info entry".to_string(), None),
+        ];
+        journal.apply(0, entries);
+        assert_eq!(
+            first_rows(&journal),
+            ["\x1b[31merror entry\x1b[0m", "info entry"]
+        );
+
+        let mut tinted = viewer("char", 20);
+        tinted.sources[0].tint = Some("2".to_string());
+        let lines = vec![
+            Update::Line("\x1b[1mbold".to_string()),
+            Update::Line("plain".to_string()),
+        ];
+        tinted.apply(0, lines);
+        assert_eq!(
+            first_rows(&tinted),
+            ["\x1b[2m\x1b[1mbold\x1b[0m", "\x1b[2mplain\x1b[0m"]
+        );
+    }
+
+    #[test]
+    fn search_while_following() {
+        let mut viewer = viewer("char", 20);
+        for n in 1..=30 {
+            let line = viewer.make_line(0, format!("line {n}"), None);
+            viewer.add_line(line);
+        }
+        let prompt = Prompt {
+            kind: '/',
+            text: "line 1".to_string(),
+            origin: None,
+        };
+        // The matches are above the last page
+        assert_eq!(viewer.submit_search(&prompt), None);
+        assert_eq!(viewer.search.as_ref().unwrap().line, Some(0));
+        viewer.next_match(false);
+        assert_eq!(viewer.search.as_ref().unwrap().line, Some(9));
+        // Searching backward starts at the end
+        let prompt = Prompt {
+            kind: '?',
+            ..prompt
+        };
+        assert_eq!(viewer.submit_search(&prompt), None);
+        assert_eq!(viewer.search.as_ref().unwrap().line, Some(18));
+    }
+
+    #[test]
+    fn char_breaks() {
+        // The last column is left empty
+        let viewer = viewer("char", 5);
+        assert_eq!(breaks(&viewer, "abcdefghij").rows, [4, 8]);
+        assert_eq!(breaks(&viewer, "ab中文字").rows, [3]);
+        // Escape sequences take no space
+        assert_eq!(breaks(&viewer, "ab\x1b[31mcdef").rows, [9]);
+    }
+
+    #[test]
+    fn word_breaks() {
+        let wide = viewer("word", 10);
+        assert_eq!(breaks(&wide, "hello world again").rows, [6, 12]);
+        // Words that are too long are broken like with `char`
+        assert_eq!(breaks(&wide, "a abcdefghijkl").rows, [2, 11]);
+        // Whitespace right before a character that never fits
+        let narrow = viewer("word", 2);
+        assert_eq!(breaks(&narrow, "a 中b中c中d").rows, [1, 2, 3, 4, 5, 6, 7]);
+    }
+
+    #[test]
+    fn no_breaks() {
+        let mut viewer = viewer("none", 5);
+        let line = breaks(&viewer, "abcdefgh");
+        assert!(line.rows.is_empty());
+        assert_eq!((line.first, line.cut), (0, Some(3)));
+        assert_eq!(breaks(&viewer, "abc").cut, None);
+        viewer.hscroll = 2;
+        let line = breaks(&viewer, "abcdefgh");
+        assert_eq!((line.first, line.cut), (2, Some(5)));
+        // A wide character that does not fit is replaced as well
+        viewer.hscroll = 0;
+        assert_eq!(breaks(&viewer, "abc中").cut, Some(3));
+    }
+}
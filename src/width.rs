@@ -0,0 +1,85 @@
+use crate::escape;
+use unicode_segmentation::UnicodeSegmentation;
+use unicode_width::UnicodeWidthChar;
+
+/// Returns the number of cells each character of `line` takes up. The
+/// first character of a grapheme cluster gets the width of the whole
+/// cluster, the others and escape sequences get zero.
+pub fn cell_widths(line: &[char]) -> Vec<usize> {
+    let mut widths = vec![0; line.len()];
+    let mut i = 0;
+    while i < line.len() {
+        if line[i] == '\x1b' {
+            i += escape::parse(line, i).length;
+            continue;
+        }
+        // Text up to the next escape sequence
+        let end = line[i..]
+            .iter()
+            .position(|&c| c == '\x1b')
+            .map_or(line.len(), |length| i + length);
+        let text: String = line[i..end].iter().collect();
+        for cluster in text.graphemes(true) {
+            widths[i] = cluster_width(cluster);
+            i += cluster.chars().count();
+        }
+    }
+    widths
+}
+
+/// Width of a grapheme cluster as terminals usually draw it, which is the
+/// width of its first character unless it is turned into an emoji.
+fn cluster_width(cluster: &str) -> usize {
+    let mut chars = cluster.chars();
+    let first = chars.next().map_or(0, |c| c.width().unwrap_or(1));
+    let regional_indicator = |c: char| ('\u{1f1e6}'..='\u{1f1ff}').contains(&c);
+    // Emoji presentation selector, or a pair of regional indicators that
+    // forms a flag
+    if cluster.contains('\u{fe0f}')
+        || (cluster.chars().count() == 2 && cluster.chars().all(regional_indicator))
+    {
+        first.max(2)
+    } else {
+        first
+    }
+}
+
+/// Replaces tabs by spaces up to the next multiple of `tab_width` cells,
+/// also returns the index in `chars` that each character comes from.
+pub fn expand_tabs(chars: &[char], tab_width: usize) -> (Vec<char>, Vec<usize>) {
+    if !chars.contains(&'\t') {
+        return (chars.to_vec(), (0..chars.len()).collect());
+    }
+    let widths = cell_widths(chars);
+    let mut result = Vec::with_capacity(chars.len());
+    let mut origins = Vec::with_capacity(chars.len());
+    let mut column = 0;
+    for (i, (&c, &width)) in chars.iter().zip(&widths).enumerate() {
+        if c == '\t' {
+            let spaces = tab_width - column % tab_width;
+            result.extend(std::iter::repeat_n(' ', spaces));
+            origins.extend(std::iter::repeat_n(i, spaces));
+            column += spaces;
+        } else {
+            result.push(c);
+            origins.push(i);
+            column += width;
+        }
+    }
+    (result, origins)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn expand_tabs_keeps_origins() {
+        let chars: Vec<char> = "a\tb\x1b[1m\tc".chars().collect();
+        let (expanded, origins) = expand_tabs(&chars, 4);
+        let expanded: String = expanded.into_iter().collect();
+        // Escape sequences take no cells
+        assert_eq!(expanded, "a   b\x1b[1m   c");
+        assert_eq!(origins, [0, 1, 1, 1, 2, 3, 4, 5, 6, 7, 7, 7, 8]);
+    }
+}